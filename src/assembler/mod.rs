@@ -0,0 +1,892 @@
+//! Assembles source written with the mnemonics [`Instruction`] displays as.
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! SPEED = 3               ; constants (`SPEED equ 3` works too)
+//!
+//! start:                  ; labels
+//!     LD V0, SPEED
+//!     LD I, sprite
+//!     DRW V0, V1, 4
+//!     JP start
+//!
+//! sprite:
+//!     db #F0, $90, 0x90, %11110000
+//!     dw #1234            ; big-endian words
+//!     include "font.asm"  ; relative to the including file
+//! ```
+//!
+//! Numbers are decimal unless prefixed with `#`, `$` or `0x` (hex), or `%` or `0b`
+//! (binary). Operands that take a value accept sums and differences of numbers,
+//! labels and constants, like `sprite + 5`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{error, fmt, fs};
+
+use crate::cpu::{AddressingMode, Instruction, Opcode};
+use crate::disassembler::PROGRAM_START;
+
+// how deep includes and constants referring to other constants can nest
+const MAX_DEPTH: usize = 32;
+
+// operands and directives, which can't be used as labels or constants
+const KEYWORDS: [&str; 13] = [
+    "I", "DT", "ST", "K", "F", "B", "HF", "R", "LONG", "EQU", "DB", "DW", "INCLUDE",
+];
+
+const MNEMONICS: [(&str, Opcode); 29] = [
+    ("CLS", Opcode::CLS),
+    ("RET", Opcode::RET),
+    ("SYS", Opcode::SYS),
+    ("JP", Opcode::JP),
+    ("CALL", Opcode::CALL),
+    ("SE", Opcode::SE),
+    ("LD", Opcode::LD),
+    ("ADD", Opcode::ADD),
+    ("OR", Opcode::OR),
+    ("AND", Opcode::AND),
+    ("XOR", Opcode::XOR),
+    ("SUB", Opcode::SUB),
+    ("SHR", Opcode::SHR),
+    ("SUBN", Opcode::SUBN),
+    ("SHL", Opcode::SHL),
+    ("SNE", Opcode::SNE),
+    ("RND", Opcode::RND),
+    ("DRW", Opcode::DRW),
+    ("SKP", Opcode::SKP),
+    ("SKNP", Opcode::SKNP),
+    ("SCD", Opcode::SCD),
+    ("SCR", Opcode::SCR),
+    ("SCL", Opcode::SCL),
+    ("EXIT", Opcode::EXIT),
+    ("LOW", Opcode::LOW),
+    ("HIGH", Opcode::HIGH),
+    ("PLANE", Opcode::PLANE),
+    ("AUDIO", Opcode::AUDIO),
+    ("PITCH", Opcode::PITCH),
+];
+
+/// An error and where it was found, lines and columns start at 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssemblerError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl error::Error for AssemblerError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Location {
+    // index in the list of files read
+    file: usize,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Comma,
+    Colon,
+    Equals,
+    LBracket,
+    RBracket,
+    Plus,
+    Minus,
+}
+
+// a token and its column
+type Spanned = (Token, usize);
+// the column of an error and its message
+type ParseError = (usize, String);
+
+// split a line into tokens along with their column
+fn tokenize(line: &str) -> Result<Vec<Spanned>, ParseError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        let token = match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '=' => Token::Equals,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '"' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&c| c == '"')
+                    .ok_or((column, "unterminated string".to_string()))?;
+                let string = chars[i + 1..i + 1 + end].iter().collect();
+                i += end + 2;
+                tokens.push((Token::Str(string), column));
+                continue;
+            }
+            c if c.is_alphanumeric() || "_.#$%".contains(c) => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|&&c| c.is_alphanumeric() || "_.#$%".contains(c))
+                    .count();
+                let word: String = chars[i..i + len].iter().collect();
+                i += len;
+
+                let starts_number = c.is_ascii_digit() || "#$%".contains(c);
+                let token = if starts_number {
+                    Token::Number(
+                        parse_number(&word).ok_or((column, format!("invalid number: {}", word)))?,
+                    )
+                } else {
+                    Token::Ident(word)
+                };
+                tokens.push((token, column));
+                continue;
+            }
+            c => return Err((column, format!("unexpected character: {}", c))),
+        };
+
+        tokens.push((token, column));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    let lower = word.to_ascii_lowercase();
+    let (digits, radix) = if let Some(hex) = lower
+        .strip_prefix('#')
+        .or_else(|| lower.strip_prefix('$'))
+        .or_else(|| lower.strip_prefix("0x"))
+    {
+        (hex, 16)
+    } else if let Some(binary) = lower.strip_prefix('%').or_else(|| lower.strip_prefix("0b")) {
+        (binary, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+
+    i64::from_str_radix(digits, radix).ok()
+}
+
+fn register(name: &str) -> Option<usize> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(x), None) | (Some('V'), Some(x), None) => {
+            x.to_digit(16).map(|x| x as usize)
+        }
+        _ => None,
+    }
+}
+
+/// A sum of numbers and symbols
+#[derive(Debug, Clone, PartialEq)]
+struct Expr {
+    // each term and whether it is subtracted
+    terms: Vec<(bool, Term)>,
+    column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Number(i64),
+    Symbol(String, usize),
+}
+
+fn parse_expr(tokens: &[Spanned], column: usize) -> Result<Expr, ParseError> {
+    let mut terms = Vec::new();
+    let mut tokens = tokens.iter().peekable();
+    let mut negative = false;
+
+    if let Some((Token::Minus, _)) = tokens.peek() {
+        negative = true;
+        tokens.next();
+    }
+
+    loop {
+        let term = match tokens.next() {
+            Some((Token::Number(n), _)) => Term::Number(*n),
+            Some((Token::Ident(name), column)) => Term::Symbol(name.clone(), *column),
+            Some((_, column)) => return Err((*column, "expected a value".to_string())),
+            None => return Err((column, "missing a value".to_string())),
+        };
+        terms.push((negative, term));
+
+        negative = match tokens.next() {
+            Some((Token::Plus, _)) => false,
+            Some((Token::Minus, _)) => true,
+            Some((_, column)) => return Err((*column, "expected + or -".to_string())),
+            None => break,
+        };
+    }
+
+    Ok(Expr { terms, column })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Register(usize),
+    // Vx-Vy
+    Range(usize, usize),
+    I,
+    // [I]
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    B,
+    HF,
+    R,
+    // LONG addr, the 16 bits XO-CHIP address
+    Long(Expr),
+    Value(Expr),
+}
+
+fn parse_operand(tokens: &[Spanned]) -> Result<Operand, ParseError> {
+    let column = tokens[0].1;
+    let ident = |n: usize| match tokens.get(n) {
+        Some((Token::Ident(name), _)) => Some(name.to_ascii_uppercase()),
+        _ => None,
+    };
+
+    let operand = match (&tokens[0].0, tokens.len()) {
+        (Token::LBracket, 3) if ident(1).as_deref() == Some("I") => match tokens[2].0 {
+            Token::RBracket => Operand::IndirectI,
+            _ => return Err((tokens[2].1, "expected ]".to_string())),
+        },
+        (Token::Ident(name), 1) if register(name).is_some() => {
+            Operand::Register(register(name).unwrap())
+        }
+        (Token::Ident(name), 3) if register(name).is_some() && tokens[1].0 == Token::Minus => {
+            let y = ident(2)
+                .and_then(|name| register(&name))
+                .ok_or((tokens[2].1, "expected a register".to_string()))?;
+            Operand::Range(register(name).unwrap(), y)
+        }
+        (Token::Ident(_), 1) => match ident(0).unwrap().as_str() {
+            "I" => Operand::I,
+            "DT" => Operand::DT,
+            "ST" => Operand::ST,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "B" => Operand::B,
+            "HF" => Operand::HF,
+            "R" => Operand::R,
+            _ => Operand::Value(parse_expr(tokens, column)?),
+        },
+        (Token::Ident(_), _) if ident(0).as_deref() == Some("LONG") => {
+            Operand::Long(parse_expr(&tokens[1..], column)?)
+        }
+        _ => Operand::Value(parse_expr(tokens, column)?),
+    };
+
+    Ok(operand)
+}
+
+// split the tokens on commas, each part must hold something
+fn split_operands(tokens: &[Spanned], column: usize) -> Result<Vec<&[Spanned]>, ParseError> {
+    if tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let parts: Vec<_> = tokens.split(|(token, _)| *token == Token::Comma).collect();
+    for (n, part) in parts.iter().enumerate() {
+        if part.is_empty() {
+            // point at the comma before the missing operand, or the mnemonic
+            let comma = tokens
+                .iter()
+                .filter(|(token, _)| *token == Token::Comma)
+                .nth(n.saturating_sub(1))
+                .map_or(column, |&(_, column)| column);
+            return Err((comma, "missing an operand".to_string()));
+        }
+    }
+
+    Ok(parts)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Statement {
+    Instruction(Opcode, Vec<(Operand, usize)>),
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction(_, operands) => match operands.as_slice() {
+                [(Operand::I, _), (Operand::Long(_), _)] => 4,
+                _ => 2,
+            },
+            Statement::Bytes(values) => values.len(),
+            Statement::Words(values) => values.len() * 2,
+        }
+    }
+}
+
+enum Symbol {
+    Address(u16),
+    Constant(Expr, Location),
+}
+
+/// Two-pass assembler: the first pass reads every file and places the labels, the
+/// second one evaluates the operands and encodes the program.
+pub struct Assembler {
+    origin: u16,
+
+    files: Vec<String>,
+    symbols: HashMap<String, (Symbol, Location)>,
+    // every statement, with its address
+    statements: Vec<(u16, Statement, Location)>,
+    // address of the next statement
+    address: usize,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self {
+            origin: PROGRAM_START,
+            files: Vec::new(),
+            symbols: HashMap::new(),
+            statements: Vec::new(),
+            address: usize::from(PROGRAM_START),
+        }
+    }
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Address the program is loaded at, `0x200` by default
+    pub fn with_origin(mut self, origin: u16) -> Self {
+        self.origin = origin;
+        self.address = usize::from(origin);
+        self
+    }
+
+    /// Assemble `source`, with includes relative to the working directory
+    pub fn assemble(mut self, source: &str) -> Result<Vec<u8>, AssemblerError> {
+        self.read("<input>", Path::new(""), source, 0)?;
+        self.encode()
+    }
+
+    pub fn assemble_file<P: AsRef<Path>>(mut self, path: P) -> Result<Vec<u8>, AssemblerError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|error| AssemblerError {
+            file: path.display().to_string(),
+            line: 0,
+            column: 0,
+            message: format!("could not read the file: {}", error),
+        })?;
+
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        self.read(&path.display().to_string(), directory, &source, 0)?;
+        self.encode()
+    }
+
+    fn error(&self, location: Location, message: String) -> AssemblerError {
+        AssemblerError {
+            file: self.files[location.file].clone(),
+            line: location.line,
+            column: location.column,
+            message,
+        }
+    }
+
+    // first pass over a file: place the labels and size the statements
+    fn read(
+        &mut self,
+        name: &str,
+        directory: &Path,
+        source: &str,
+        depth: usize,
+    ) -> Result<(), AssemblerError> {
+        let file = self.files.len();
+        self.files.push(name.to_string());
+
+        for (n, line) in source.lines().enumerate() {
+            let at = |column| Location {
+                file,
+                line: n + 1,
+                column,
+            };
+
+            let tokens =
+                tokenize(line).map_err(|(column, message)| self.error(at(column), message))?;
+            self.read_line(&tokens, directory, depth, at)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_line<F>(
+        &mut self,
+        mut tokens: &[Spanned],
+        directory: &Path,
+        depth: usize,
+        at: F,
+    ) -> Result<(), AssemblerError>
+    where
+        F: Fn(usize) -> Location,
+    {
+        let fail =
+            |this: &Self, (column, message): (usize, String)| this.error(at(column), message);
+
+        // a label, maybe followed by a statement
+        if let [(Token::Ident(name), column), (Token::Colon, _), ..] = tokens {
+            if self.address > 0xFFFF {
+                return Err(fail(
+                    self,
+                    (*column, "the program is too large".to_string()),
+                ));
+            }
+            self.define(name, Symbol::Address(self.address as u16), at(*column))?;
+            tokens = &tokens[2..];
+        }
+
+        let (name, column) = match tokens.first() {
+            Some((Token::Ident(name), column)) => (name, *column),
+            Some((_, column)) => {
+                return Err(fail(self, (*column, "expected a mnemonic".to_string())))
+            }
+            None => return Ok(()),
+        };
+
+        // constants, `NAME = value` or `NAME equ value`
+        match tokens.get(1) {
+            Some((Token::Equals, _)) => {
+                let expr = parse_expr(&tokens[2..], tokens[1].1 + 1).map_err(|e| fail(self, e))?;
+                return self.define(name, Symbol::Constant(expr, at(column)), at(column));
+            }
+            Some((Token::Ident(equ), equ_column)) if equ.eq_ignore_ascii_case("equ") => {
+                let expr = parse_expr(&tokens[2..], equ_column + 3).map_err(|e| fail(self, e))?;
+                return self.define(name, Symbol::Constant(expr, at(column)), at(column));
+            }
+            _ => {}
+        }
+
+        let operands = split_operands(&tokens[1..], column).map_err(|e| fail(self, e))?;
+        let statement = match name.to_ascii_uppercase().as_str() {
+            "INCLUDE" => {
+                let path = match operands.as_slice() {
+                    [[(Token::Str(path), _)]] => directory.join(path),
+                    _ => return Err(fail(self, (column, "expected a quoted path".to_string()))),
+                };
+                return self.include(&path, depth, at(column));
+            }
+            "DB" | "DW" => {
+                let values = operands
+                    .iter()
+                    .map(|operand| parse_expr(operand, operand[0].1))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| fail(self, e))?;
+                if values.is_empty() {
+                    return Err(fail(self, (column, "missing a value".to_string())));
+                }
+
+                if name.eq_ignore_ascii_case("db") {
+                    Statement::Bytes(values)
+                } else {
+                    Statement::Words(values)
+                }
+            }
+            mnemonic => {
+                let opcode = MNEMONICS
+                    .iter()
+                    .find(|(name, _)| *name == mnemonic)
+                    .map(|&(_, opcode)| opcode)
+                    .ok_or_else(|| fail(self, (column, format!("unknown mnemonic: {}", name))))?;
+
+                let operands = operands
+                    .iter()
+                    .map(|operand| parse_operand(operand).map(|parsed| (parsed, operand[0].1)))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| fail(self, e))?;
+                Statement::Instruction(opcode, operands)
+            }
+        };
+
+        if self.address + statement.size() > 0x10000 {
+            return Err(fail(self, (column, "the program is too large".to_string())));
+        }
+        let address = self.address as u16;
+        self.address += statement.size();
+        self.statements.push((address, statement, at(column)));
+
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        path: &Path,
+        depth: usize,
+        location: Location,
+    ) -> Result<(), AssemblerError> {
+        if depth >= MAX_DEPTH {
+            return Err(self.error(location, "includes are nested too deep".to_string()));
+        }
+
+        let source = fs::read_to_string(path).map_err(|error| {
+            self.error(
+                location,
+                format!("could not include {}: {}", path.display(), error),
+            )
+        })?;
+
+        let directory = path.parent().map(PathBuf::from).unwrap_or_default();
+        self.read(&path.display().to_string(), &directory, &source, depth + 1)
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        symbol: Symbol,
+        location: Location,
+    ) -> Result<(), AssemblerError> {
+        let reserved = MNEMONICS
+            .iter()
+            .map(|&(mnemonic, _)| mnemonic)
+            .chain(KEYWORDS.iter().copied())
+            .any(|keyword| keyword.eq_ignore_ascii_case(name));
+        if reserved || register(name).is_some() {
+            return Err(self.error(location, format!("reserved name: {}", name)));
+        }
+        if let Some((_, previous)) = self.symbols.get(name) {
+            let message = format!(
+                "{} is already defined at {}:{}",
+                name, self.files[previous.file], previous.line
+            );
+            return Err(self.error(location, message));
+        }
+
+        self.symbols.insert(name.to_string(), (symbol, location));
+        Ok(())
+    }
+
+    fn evaluate(
+        &self,
+        expr: &Expr,
+        location: Location,
+        depth: usize,
+    ) -> Result<i64, AssemblerError> {
+        let mut value = 0i64;
+
+        for (negative, term) in expr.terms.iter() {
+            let term = match term {
+                Term::Number(n) => *n,
+                Term::Symbol(name, column) => {
+                    let location = Location {
+                        column: *column,
+                        ..location
+                    };
+                    match self.symbols.get(name) {
+                        Some((Symbol::Address(addr), _)) => i64::from(*addr),
+                        Some((Symbol::Constant(expr, defined), _)) if depth < MAX_DEPTH => {
+                            self.evaluate(expr, *defined, depth + 1)?
+                        }
+                        Some(_) => {
+                            return Err(self.error(
+                                location,
+                                format!("{} is defined in terms of itself", name),
+                            ))
+                        }
+                        None => {
+                            return Err(self.error(location, format!("undefined symbol: {}", name)))
+                        }
+                    }
+                }
+            };
+
+            value = if *negative {
+                value.wrapping_sub(term)
+            } else {
+                value.wrapping_add(term)
+            };
+        }
+
+        Ok(value)
+    }
+
+    // evaluate `expr`, checking it fits in `max`
+    fn value(
+        &self,
+        expr: &Expr,
+        location: Location,
+        max: i64,
+        what: &str,
+    ) -> Result<i64, AssemblerError> {
+        let location = Location {
+            column: expr.column,
+            ..location
+        };
+        let value = self.evaluate(expr, location, 0)?;
+
+        if (0..=max).contains(&value) {
+            Ok(value)
+        } else {
+            Err(self.error(location, format!("{} out of range: {}", what, value)))
+        }
+    }
+
+    // turn the operands of an instruction into its addressing mode
+    fn addressing_mode(
+        &self,
+        opcode: Opcode,
+        operands: &[(Operand, usize)],
+        location: Location,
+    ) -> Result<AddressingMode, AssemblerError> {
+        use AddressingMode::*;
+        use Operand as O;
+
+        let byte = |expr| self.value(expr, location, 0xFF, "byte").map(|v| v as u8);
+        let nibble = |expr| self.value(expr, location, 0xF, "nibble").map(|v| v as u8);
+        let addr = |expr| {
+            self.value(expr, location, 0xFFF, "address")
+                .map(|v| v as u16)
+        };
+        let long = |expr| {
+            self.value(expr, location, 0xFFFF, "address")
+                .map(|v| v as u16)
+        };
+
+        let operands: Vec<&Operand> = operands.iter().map(|(operand, _)| operand).collect();
+        let mode = match (opcode, operands.as_slice()) {
+            (
+                Opcode::CLS
+                | Opcode::RET
+                | Opcode::SCR
+                | Opcode::SCL
+                | Opcode::EXIT
+                | Opcode::LOW
+                | Opcode::HIGH
+                | Opcode::AUDIO,
+                [],
+            ) => Implicit,
+            (Opcode::SCD | Opcode::PLANE, [O::Value(n)]) => Nibble(nibble(n)?),
+            (Opcode::SYS | Opcode::JP | Opcode::CALL, [O::Value(a)]) => Addr(addr(a)?),
+            (Opcode::JP, [O::Register(0), O::Value(a)]) => V0Addr(addr(a)?),
+
+            (
+                Opcode::SE | Opcode::SNE | Opcode::LD | Opcode::ADD | Opcode::RND,
+                [O::Register(x), O::Value(kk)],
+            ) => VxImediate(*x, byte(kk)?),
+            (
+                Opcode::SE
+                | Opcode::SNE
+                | Opcode::LD
+                | Opcode::OR
+                | Opcode::AND
+                | Opcode::XOR
+                | Opcode::ADD
+                | Opcode::SUB
+                | Opcode::SHR
+                | Opcode::SUBN
+                | Opcode::SHL,
+                [O::Register(x), O::Register(y)],
+            ) => VxVy(*x, *y),
+            // `SHR Vx` shifts Vx in place, whatever the quirks
+            (Opcode::SHR | Opcode::SHL, [O::Register(x)]) => VxVy(*x, *x),
+            (Opcode::DRW, [O::Register(x), O::Register(y), O::Value(n)]) => {
+                VxVyImediate(*x, *y, nibble(n)?)
+            }
+            (Opcode::SKP | Opcode::SKNP | Opcode::PITCH, [O::Register(x)]) => Vx(*x),
+
+            (Opcode::LD, [O::Register(x), O::DT]) => VxDT(*x),
+            (Opcode::LD, [O::Register(x), O::K]) => VxKey(*x),
+            (Opcode::LD, [O::Register(x), O::IndirectI]) => VxMem(*x),
+            (Opcode::LD, [O::Register(x), O::R]) => VxR(*x),
+            (Opcode::LD, [O::Range(x, y), O::IndirectI]) => VxVyMem(*x, *y),
+            (Opcode::LD, [O::I, O::Value(a)]) => IAddr(addr(a)?),
+            (Opcode::LD, [O::I, O::Long(a)]) => ILongAddr(long(a)?),
+            (Opcode::ADD, [O::I, O::Register(x)]) => IVx(*x),
+            (Opcode::LD, [O::F, O::Register(x)]) => FVx(*x),
+            (Opcode::LD, [O::B, O::Register(x)]) => BVx(*x),
+            (Opcode::LD, [O::HF, O::Register(x)]) => HFVx(*x),
+            (Opcode::LD, [O::R, O::Register(x)]) => RVx(*x),
+            (Opcode::LD, [O::DT, O::Register(x)]) => DTVx(*x),
+            (Opcode::LD, [O::ST, O::Register(x)]) => STVx(*x),
+            (Opcode::LD, [O::IndirectI, O::Register(x)]) => MemVx(*x),
+            (Opcode::LD, [O::IndirectI, O::Range(x, y)]) => MemVxVy(*x, *y),
+
+            _ => return Err(self.error(location, format!("invalid operands for {}", opcode))),
+        };
+
+        Ok(mode)
+    }
+
+    // second pass: evaluate and encode every statement
+    fn encode(self) -> Result<Vec<u8>, AssemblerError> {
+        let mut rom = Vec::with_capacity(self.address - usize::from(self.origin));
+
+        for (_, statement, location) in self.statements.iter() {
+            match statement {
+                Statement::Instruction(opcode, operands) => {
+                    let mode = self.addressing_mode(*opcode, operands, *location)?;
+                    let instruction = Instruction(*opcode, mode);
+                    let bytes = instruction.to_bytes().map_err(|error| {
+                        self.error(
+                            *location,
+                            format!("can't encode {}: {}", instruction, error),
+                        )
+                    })?;
+                    rom.extend_from_slice(&bytes);
+                }
+                Statement::Bytes(values) => {
+                    for value in values {
+                        // negative bytes are allowed, as two's complement
+                        let location = Location {
+                            column: value.column,
+                            ..*location
+                        };
+                        match self.evaluate(value, location, 0)? {
+                            byte @ -0x80..=0xFF => rom.push(byte as u8),
+                            byte => {
+                                return Err(
+                                    self.error(location, format!("byte out of range: {}", byte))
+                                )
+                            }
+                        }
+                    }
+                }
+                Statement::Words(values) => {
+                    for value in values {
+                        let word = self.value(value, *location, 0xFFFF, "word")? as u16;
+                        rom.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+            }
+        }
+
+        Ok(rom)
+    }
+}
+
+/// Assemble a program loaded at `0x200`
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError> {
+    Assembler::new().assemble(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::disassemble;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let error = assemble(source).unwrap_err();
+        (error.line, error.column, error.message)
+    }
+
+    #[test]
+    fn assembles_labels_constants_and_data() {
+        let source = "
+            SPEED equ 3
+            OFFSET = sprite + 1 ; forward references work
+            start:  LD V0, SPEED
+                    ld i, OFFSET
+                    DRW V0, V1, 4
+                    JP start
+            sprite: db #F0, $90, %10010000, -1
+                    dw 0x1234
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![
+                0x60, 0x03, 0xA2, 0x09, 0xD0, 0x14, 0x12, 0x00, 0xF0, 0x90, 0x90, 0xFF, 0x12, 0x34
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_line_and_column() {
+        assert_eq!(
+            error("CLS\n  LD V0, 256"),
+            (2, 10, "byte out of range: 256".to_string())
+        );
+        assert_eq!(
+            error("  JP nowhere"),
+            (1, 6, "undefined symbol: nowhere".to_string())
+        );
+        assert_eq!(
+            error("CLS\nCLS\n   FOO V0"),
+            (3, 4, "unknown mnemonic: FOO".to_string())
+        );
+        assert_eq!(
+            error("  DRW V0, 5, V1"),
+            (1, 3, "invalid operands for DRW".to_string())
+        );
+        assert_eq!(error("a: CLS\na: CLS").0, 2);
+        assert_eq!(error("dt = 3").2, "reserved name: dt");
+    }
+
+    #[test]
+    fn includes_files_relative_to_the_includer() {
+        let directory = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        fs::write(
+            directory.join("main.asm"),
+            "CALL draw\ninclude \"lib/draw.asm\"",
+        )
+        .unwrap();
+        fs::write(directory.join("lib/draw.asm"), "draw: RET").unwrap();
+
+        let rom = Assembler::new().assemble_file(directory.join("main.asm"));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(rom.unwrap(), vec![0x22, 0x02, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn reassembles_disassembled_roms() {
+        let mut rng = StdRng::seed_from_u64(0xC8);
+
+        for _ in 0..500 {
+            let len = rng.gen_range(1..256);
+            let mut rom: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            // mostly valid code, with jumps and calls kept inside the rom
+            if rng.gen_bool(0.5) {
+                for word in rom.chunks_mut(2).filter(|word| word.len() == 2) {
+                    if word[0] >> 4 == 1 || word[0] >> 4 == 2 {
+                        let target = 0x200 + rng.gen_range(0..len as u16);
+                        word[0] = word[0] & 0xF0 | (target >> 8) as u8;
+                        word[1] = target as u8;
+                    }
+                }
+            }
+
+            let source = disassemble(&rom).source();
+            assert_eq!(assemble(&source).unwrap(), rom, "\n{}", source);
+        }
+    }
+}
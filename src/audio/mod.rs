@@ -0,0 +1,297 @@
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+/// Receives the buzzer state, driven by the cpu sound timer
+pub trait AudioSink {
+    /// The buzzer started (`true`) or stopped (`false`) sounding
+    fn set_beeping(&mut self, beeping: bool);
+
+    /// The XO-CHIP audio pattern or its pitch changed
+    fn set_pattern(&mut self, _pattern: &[u8; 16], _pitch: u8) {}
+
+    /// No pattern is loaded, the plain tone plays again
+    fn clear_pattern(&mut self) {}
+
+    /// Called on every timer tick (60Hz)
+    fn tick(&mut self) {}
+}
+
+/// Discards every sound
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn set_beeping(&mut self, _beeping: bool) {}
+}
+
+/// A buzzer edge, with the timer tick it happened on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioEvent {
+    Start(u64),
+    Stop(u64),
+}
+
+/// Records every buzzer edge, so tests can assert when the buzzer sounded.
+///
+/// Clones share the same recording, keep one around before handing the sink to the cpu.
+#[derive(Debug, Default, Clone)]
+pub struct RecordingSink {
+    ticks: Rc<RefCell<u64>>,
+    events: Rc<RefCell<Vec<AudioEvent>>>,
+}
+
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<AudioEvent> {
+        self.events.borrow().clone()
+    }
+
+    // whether the buzzer is sounding right now
+    pub fn is_beeping(&self) -> bool {
+        matches!(self.events.borrow().last(), Some(AudioEvent::Start(_)))
+    }
+}
+
+impl AudioSink for RecordingSink {
+    fn set_beeping(&mut self, beeping: bool) {
+        let tick = *self.ticks.borrow();
+        let event = if beeping {
+            AudioEvent::Start(tick)
+        } else {
+            AudioEvent::Stop(tick)
+        };
+
+        self.events.borrow_mut().push(event);
+    }
+
+    fn tick(&mut self) {
+        *self.ticks.borrow_mut() += 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+/// Configuration of the generated buzzer tone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioConfig {
+    // tone frequency in Hz
+    pub frequency: f32,
+    pub waveform: Waveform,
+    // from 0.0 (mute) to 1.0
+    pub volume: f32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Sample generator for the buzzer, for sinks that need to produce actual audio.
+///
+/// Plays the configured tone, or the XO-CHIP audio pattern once a program loads one.
+#[derive(Debug, Clone)]
+pub struct Buzzer {
+    pub config: AudioConfig,
+    beeping: bool,
+    pattern: Option<([u8; 16], u8)>,
+    // position in the current period (or pattern), from 0.0 to 1.0
+    phase: f32,
+}
+
+impl Buzzer {
+    pub fn new(config: AudioConfig) -> Self {
+        Self {
+            config,
+            beeping: false,
+            pattern: None,
+            phase: 0.0,
+        }
+    }
+
+    pub fn set_beeping(&mut self, beeping: bool) {
+        self.beeping = beeping;
+    }
+
+    pub fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.pattern = Some((*pattern, pitch));
+    }
+    pub fn clear_pattern(&mut self) {
+        self.pattern = None;
+    }
+
+    // fill `samples` with audio at `sample_rate` Hz
+    pub fn fill(&mut self, samples: &mut [f32], sample_rate: u32) {
+        if !self.beeping {
+            samples.iter_mut().for_each(|sample| *sample = 0.0);
+            return;
+        }
+
+        let volume = self.config.volume;
+        match self.pattern {
+            Some((pattern, pitch)) => {
+                // the 128 bits pattern is played at 4000 * 2^((pitch - 64) / 48) bits per second
+                let rate = 4000.0 * 2f32.powf((f32::from(pitch) - 64.0) / 48.0);
+                let step = rate / 128.0 / sample_rate as f32;
+
+                for sample in samples.iter_mut() {
+                    let bit = (self.phase * 128.0) as usize % 128;
+                    let lit = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+
+                    *sample = if lit { volume } else { -volume };
+                    self.phase = (self.phase + step).fract();
+                }
+            }
+            None => {
+                let step = self.config.frequency / sample_rate as f32;
+
+                for sample in samples.iter_mut() {
+                    let value = match self.config.waveform {
+                        Waveform::Square => {
+                            if self.phase < 0.5 {
+                                1.0
+                            } else {
+                                -1.0
+                            }
+                        }
+                        Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+                        Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+                        Waveform::Sine => (2.0 * PI * self.phase).sin(),
+                    };
+
+                    *sample = value * volume;
+                    self.phase = (self.phase + step).fract();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AudioConfig, AudioEvent, AudioSink, Buzzer, RecordingSink};
+    use crate::cpu::{AddressingMode, Instruction, Opcode, Quirks, CPU};
+    use crate::mmu::MMU;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // feeds a buzzer the tests can listen to
+    struct BuzzerSink(Rc<RefCell<Buzzer>>);
+
+    impl AudioSink for BuzzerSink {
+        fn set_beeping(&mut self, beeping: bool) {
+            self.0.borrow_mut().set_beeping(beeping);
+        }
+        fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+            self.0.borrow_mut().set_pattern(pattern, pitch);
+        }
+        fn clear_pattern(&mut self) {
+            self.0.borrow_mut().clear_pattern();
+        }
+    }
+
+    // whether the buzzer plays the square tone rather than the (empty) pattern
+    fn plays_tone(buzzer: &RefCell<Buzzer>) -> bool {
+        let mut samples = [0.0; 200];
+        buzzer.borrow_mut().fill(&mut samples, 44100);
+
+        samples.iter().any(|&sample| sample > 0.0) && samples.iter().any(|&sample| sample < 0.0)
+    }
+
+    fn cpu_with_sink() -> (CPU, RecordingSink) {
+        let mut mmu = MMU::default();
+        mmu.lock_rom();
+
+        let mut cpu = CPU::new(mmu, Quirks::default());
+        let sink = RecordingSink::new();
+        cpu.set_audio_sink(Box::new(sink.clone()));
+
+        (cpu, sink)
+    }
+
+    fn load_sound_timer(cpu: &mut CPU, value: u8) {
+        cpu.v[0] = value;
+        cpu.execute(Instruction(Opcode::LD, AddressingMode::STVx(0)))
+            .unwrap();
+    }
+
+    #[test]
+    fn buzzer_sounds_while_the_sound_timer_is_running() {
+        let (mut cpu, sink) = cpu_with_sink();
+
+        cpu.tick_timers();
+        load_sound_timer(&mut cpu, 3);
+        assert!(sink.is_beeping());
+
+        for _ in 0..3 {
+            cpu.tick_timers();
+        }
+        assert!(!sink.is_beeping());
+        assert_eq!(
+            sink.events(),
+            vec![AudioEvent::Start(1), AudioEvent::Stop(4)]
+        );
+    }
+
+    #[test]
+    fn reloading_a_running_timer_does_not_retrigger() {
+        let (mut cpu, sink) = cpu_with_sink();
+
+        load_sound_timer(&mut cpu, 2);
+        load_sound_timer(&mut cpu, 5);
+        assert_eq!(sink.events(), vec![AudioEvent::Start(0)]);
+
+        load_sound_timer(&mut cpu, 0);
+        assert!(!sink.is_beeping());
+    }
+
+    #[test]
+    fn loading_a_state_keeps_the_tone() {
+        let (mut cpu, _) = cpu_with_sink();
+        let buzzer = Rc::new(RefCell::new(Buzzer::new(AudioConfig::default())));
+        cpu.set_audio_sink(Box::new(BuzzerSink(buzzer.clone())));
+
+        let state = cpu.save_state();
+        cpu.load_state(&state).unwrap();
+        load_sound_timer(&mut cpu, 5);
+
+        assert!(plays_tone(&buzzer));
+    }
+
+    #[test]
+    fn loading_a_state_restores_the_pattern() {
+        let (mut cpu, _) = cpu_with_sink();
+        let buzzer = Rc::new(RefCell::new(Buzzer::new(AudioConfig::default())));
+        cpu.set_audio_sink(Box::new(BuzzerSink(buzzer.clone())));
+
+        // an empty pattern at I = 0x600
+        cpu.i = 0x600;
+        cpu.execute(Instruction(Opcode::AUDIO, AddressingMode::Implicit))
+            .unwrap();
+        let state = cpu.save_state();
+
+        let (mut restored, _) = cpu_with_sink();
+        let restored_buzzer = Rc::new(RefCell::new(Buzzer::new(AudioConfig::default())));
+        restored.set_audio_sink(Box::new(BuzzerSink(restored_buzzer.clone())));
+        restored.load_state(&state).unwrap();
+        load_sound_timer(&mut restored, 5);
+
+        assert!(restored.pattern_loaded);
+        assert!(!plays_tone(&restored_buzzer));
+    }
+}
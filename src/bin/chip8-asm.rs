@@ -0,0 +1,59 @@
+extern crate chip8;
+
+use chip8::assembler::Assembler;
+
+use std::{env, fs, process};
+
+const USAGE: &str = "Usage: chip8-asm [--origin ADDR] <SOURCE> <ROM>
+
+Assembles SOURCE into the ROM file.
+
+Options:
+    --origin ADDR  hex address the ROM is loaded at (default: 200)";
+
+fn usage(error: &str) -> ! {
+    eprintln!("{}\n\n{}", error, USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let mut paths = Vec::new();
+    let mut assembler = Assembler::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--origin" => {
+                let value = args.next().unwrap_or_default();
+                match u16::from_str_radix(value.trim_start_matches("0x"), 16) {
+                    Ok(origin) => assembler = assembler.with_origin(origin),
+                    Err(_) => usage(&format!("invalid value for --origin: {}", value)),
+                }
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => usage(&format!("unknown option: {}", arg)),
+            _ => paths.push(arg),
+        }
+    }
+
+    let (source, output) = match paths.as_slice() {
+        [source, output] => (source, output),
+        _ => usage("expected a source and a rom path"),
+    };
+
+    let rom = match assembler.assemble_file(source) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+
+    if let Err(error) = fs::write(output, &rom) {
+        eprintln!("could not write {}: {}", output, error);
+        process::exit(2);
+    }
+}
@@ -0,0 +1,89 @@
+extern crate chip8;
+
+use chip8::cpu::{Quirks, CPU};
+use chip8::debugger::{Repl, Response};
+use chip8::machine::Machine;
+use chip8::mmu::MMU;
+
+use std::io::{self, BufRead, Write};
+use std::{env, process};
+
+const USAGE: &str = "Usage: chip8-debug [--quirks vip|chip48|schip|xochip] <ROM>
+
+Runs a ROM under an interactive debugger, type `help` at the prompt for the commands.";
+
+fn parse_args() -> Result<(String, Option<Quirks>), String> {
+    let mut args = env::args().skip(1);
+
+    let mut rom_path = None;
+    let mut quirks = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                quirks = Some(match args.next().as_deref() {
+                    Some("vip") => Quirks::cosmac_vip(),
+                    Some("chip48") => Quirks::chip48(),
+                    Some("schip") => Quirks::superchip(),
+                    Some("xochip") => Quirks::xo_chip(),
+                    other => return Err(format!("unknown platform: {:?}", other)),
+                })
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    Ok((rom_path.ok_or("missing the rom path")?, quirks))
+}
+
+fn main() {
+    let (rom_path, quirks) = match parse_args() {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let xo_chip = rom_path.ends_with(".xo8");
+    let mut mmu = if xo_chip {
+        MMU::xo_chip()
+    } else {
+        MMU::default()
+    };
+    if let Err(error) = mmu.load_game(&rom_path) {
+        eprintln!("could not load {}: {}", rom_path, error);
+        process::exit(2);
+    }
+
+    let quirks = quirks.unwrap_or(if xo_chip {
+        Quirks::xo_chip()
+    } else {
+        Quirks::default()
+    });
+    let mut machine = Machine::new(CPU::new(mmu, quirks));
+    let mut repl = Repl::new();
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush().unwrap();
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+
+        match repl.execute(&mut machine, &line) {
+            Ok(Response::Output(output)) if output.is_empty() => {}
+            Ok(Response::Output(output)) => println!("{}", output),
+            Ok(Response::Quit) => break,
+            Err(error) => println!("error: {}", error),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+extern crate chip8;
+
+use chip8::disassembler::{Disassembly, PROGRAM_START};
+
+use std::{env, fs, process};
+
+const USAGE: &str = "Usage: chip8-disasm [OPTIONS] <ROM>
+
+Prints the disassembly of a ROM, with the address and raw bytes of every line.
+
+Options:
+    --origin ADDR  hex address the ROM is loaded at (default: 200)
+    --source       print assembler source instead of a listing";
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let mut rom_path = None;
+    let mut origin = PROGRAM_START;
+    let mut source = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--origin" => {
+                let value = args.next().unwrap_or_default();
+                origin = match u16::from_str_radix(value.trim_start_matches("0x"), 16) {
+                    Ok(origin) => origin,
+                    Err(_) => usage(&format!("invalid value for --origin: {}", value)),
+                }
+            }
+            "--source" => source = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => usage(&format!("unknown option: {}", arg)),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    let rom_path = rom_path.unwrap_or_else(|| usage("missing the rom path"));
+    let rom = match fs::read(&rom_path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("could not read {}: {}", rom_path, error);
+            process::exit(2);
+        }
+    };
+
+    let disassembly = Disassembly::new(&rom, origin);
+    if source {
+        print!("{}", disassembly.source());
+    } else {
+        print!("{}", disassembly.listing());
+    }
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("{}\n\n{}", error, USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,472 @@
+extern crate chip8;
+
+use chip8::cpu::{Quirks, Status, CPU};
+use chip8::database::Database;
+use chip8::machine::{Machine, Timing, TIMER_FREQUENCY};
+use chip8::mmu::{MemoryMap, Protection, MMU};
+use chip8::state::crc32;
+use chip8::trace::{TraceFormat, TraceWriter};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::{env, fmt, process};
+
+const USAGE: &str = "Usage: chip8-headless [OPTIONS] <ROM>
+
+Runs a ROM without any display and dumps the final screen and registers.
+ROMs found in the database get its quirks, speed and memory map unless given.
+
+Options:
+    --frames N           run for N frames (default: 600)
+    --cycles N           run for N instructions instead of a number of frames
+    --ips N              instructions per second (default: 1200)
+    --timing MODE        fixed, or vip to take as long as a COSMAC VIP (default: fixed)
+    --memory MAP         4k, eti660 (4k loading at 0x600) or 64k (default: 4k, 64k for .xo8 roms)
+    --database DIR       read programs.json and platforms.json from DIR
+    --no-database        don't look the ROM up in the database
+    --strict-memory      fault on writes to the font and program memory
+    --quirks PLATFORM    vip, chip48, schip or xochip (default: vip, xochip for .xo8 roms)
+    --press FRAME:KEY    press the hex KEY when FRAME starts
+    --release FRAME:KEY  release the hex KEY when FRAME starts
+    --screen FORMAT      ascii, pbm or png (default: ascii)
+    --output PATH        write the screen to PATH instead of stdout
+    --registers          print the registers as JSON to stdout
+    --trace PATH         write a record of every instruction run to PATH
+    --trace-format FMT   text or json (default: text)";
+
+enum Budget {
+    Frames(u64),
+    Cycles(u64),
+}
+
+#[derive(Clone, Copy)]
+enum ScreenFormat {
+    Ascii,
+    Pbm,
+    Png,
+}
+
+struct KeyEvent {
+    frame: u64,
+    key: u8,
+    pressed: bool,
+}
+
+struct Options {
+    rom_path: String,
+    budget: Budget,
+    instructions_per_second: Option<u32>,
+    timing: Timing,
+    quirks: Option<Quirks>,
+    keys: Vec<KeyEvent>,
+    screen: ScreenFormat,
+    output: Option<String>,
+    registers: bool,
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    memory: Option<MemoryMap>,
+    database: Option<String>,
+    no_database: bool,
+    strict_memory: bool,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("missing value for {}", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", flag, value))
+}
+
+fn parse_key_event(flag: &str, value: Option<String>, pressed: bool) -> Result<KeyEvent, String> {
+    let value = value.ok_or(format!("missing value for {}", flag))?;
+    let invalid = || format!("invalid value for {}: {} (expected FRAME:KEY)", flag, value);
+
+    let mut parts = value.splitn(2, ':');
+    let frame = parts.next().and_then(|frame| frame.parse().ok());
+    let key = parts
+        .next()
+        .and_then(|key| u8::from_str_radix(key, 16).ok())
+        .filter(|&key| key <= 0xF);
+
+    match (frame, key) {
+        (Some(frame), Some(key)) => Ok(KeyEvent {
+            frame,
+            key,
+            pressed,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = env::args().skip(1);
+
+    let mut rom_path = None;
+    let mut options = Options {
+        rom_path: String::new(),
+        budget: Budget::Frames(600),
+        instructions_per_second: None,
+        timing: Timing::Fixed,
+        quirks: None,
+        keys: Vec::new(),
+        screen: ScreenFormat::Ascii,
+        output: None,
+        registers: false,
+        trace: None,
+        trace_format: TraceFormat::Text,
+        memory: None,
+        database: None,
+        no_database: false,
+        strict_memory: false,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => options.budget = Budget::Frames(parse_number(&arg, args.next())?),
+            "--cycles" => options.budget = Budget::Cycles(parse_number(&arg, args.next())?),
+            "--ips" => options.instructions_per_second = Some(parse_number(&arg, args.next())?),
+            "--timing" => {
+                options.timing = match args.next().as_deref() {
+                    Some("fixed") => Timing::Fixed,
+                    Some("vip") => Timing::CosmacVip,
+                    other => return Err(format!("unknown timing: {:?}", other)),
+                }
+            }
+            "--quirks" => {
+                options.quirks = Some(match args.next().as_deref() {
+                    Some("vip") => Quirks::cosmac_vip(),
+                    Some("chip48") => Quirks::chip48(),
+                    Some("schip") => Quirks::superchip(),
+                    Some("xochip") => Quirks::xo_chip(),
+                    other => return Err(format!("unknown platform: {:?}", other)),
+                })
+            }
+            "--press" => options.keys.push(parse_key_event(&arg, args.next(), true)?),
+            "--release" => options
+                .keys
+                .push(parse_key_event(&arg, args.next(), false)?),
+            "--screen" => {
+                options.screen = match args.next().as_deref() {
+                    Some("ascii") => ScreenFormat::Ascii,
+                    Some("pbm") => ScreenFormat::Pbm,
+                    Some("png") => ScreenFormat::Png,
+                    other => return Err(format!("unknown screen format: {:?}", other)),
+                }
+            }
+            "--output" => options.output = Some(args.next().ok_or("missing value for --output")?),
+            "--registers" => options.registers = true,
+            "--trace" => options.trace = Some(args.next().ok_or("missing value for --trace")?),
+            "--trace-format" => {
+                options.trace_format = match args.next().as_deref() {
+                    Some("text") => TraceFormat::Text,
+                    Some("json") => TraceFormat::Json,
+                    other => return Err(format!("unknown trace format: {:?}", other)),
+                }
+            }
+            "--memory" => {
+                options.memory = Some(match args.next().as_deref() {
+                    Some("4k") => MemoryMap::chip8(),
+                    Some("eti660") => MemoryMap::eti660(),
+                    Some("64k") => MemoryMap::xo_chip(),
+                    other => return Err(format!("unknown memory map: {:?}", other)),
+                })
+            }
+            "--database" => {
+                options.database = Some(args.next().ok_or("missing value for --database")?)
+            }
+            "--no-database" => options.no_database = true,
+            "--strict-memory" => options.strict_memory = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    options.rom_path = rom_path.ok_or("missing the rom path")?;
+    // apply the key events in order
+    options.keys.sort_by_key(|event| event.frame);
+
+    Ok(options)
+}
+
+// feed the key events due up to the current frame
+fn apply_keys(machine: &mut Machine, keys: &[KeyEvent], next_key: &mut usize) {
+    while let Some(event) = keys.get(*next_key) {
+        if event.frame > machine.frames() {
+            break;
+        }
+
+        if event.pressed {
+            machine.cpu.keypad.press(event.key);
+        } else {
+            machine.cpu.keypad.release(event.key);
+        }
+        *next_key += 1;
+    }
+}
+
+// bitmask of the planes lit on each pixel of the screen, row by row
+fn screen(cpu: &CPU) -> (usize, usize, Vec<u8>) {
+    let bus = &cpu.bus;
+    let (width, height) = (bus.width(), bus.height());
+
+    (width, height, bus.vram[..width * height].to_vec())
+}
+
+fn write_ascii(out: &mut dyn Write, cpu: &CPU) -> io::Result<()> {
+    const CHARS: [char; 4] = ['.', '#', '+', '@'];
+
+    let (width, _, pixels) = screen(cpu);
+    for row in pixels.chunks(width) {
+        let line: String = row
+            .iter()
+            .map(|&pixel| CHARS[usize::from(pixel & 0b11)])
+            .collect();
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+// plain (ASCII) portable bitmap, any lit plane counts as black
+fn write_pbm(out: &mut dyn Write, cpu: &CPU) -> io::Result<()> {
+    let (width, height, pixels) = screen(cpu);
+
+    writeln!(out, "P1\n{} {}", width, height)?;
+    for row in pixels.chunks(width) {
+        let line: Vec<&str> = row
+            .iter()
+            .map(|&pixel| if pixel != 0 { "1" } else { "0" })
+            .collect();
+        writeln!(out, "{}", line.join(" "))?;
+    }
+    Ok(())
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn write_png_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut chunk = kind.to_vec();
+    chunk.extend_from_slice(data);
+
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(&chunk)?;
+    out.write_all(&crc32(&chunk).to_be_bytes())
+}
+
+// 8-bit grayscale png, with the image data stored uncompressed
+fn write_png(out: &mut dyn Write, cpu: &CPU) -> io::Result<()> {
+    const SHADES: [u8; 4] = [0x00, 0xFF, 0xAA, 0x55];
+
+    let (width, height, pixels) = screen(cpu);
+
+    let mut image = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width) {
+        // no filter
+        image.push(0);
+        image.extend(row.iter().map(|&pixel| SHADES[usize::from(pixel & 0b11)]));
+    }
+
+    // zlib stream made of stored deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = image.chunks(0xFFFF);
+    let last = blocks.len() - 1;
+    for (n, block) in blocks.enumerate() {
+        let len = block.len() as u16;
+        zlib.push(u8::from(n == last));
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&image).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth, grayscale, compression, filter and interlace methods
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+    write_png_chunk(out, b"IHDR", &header)?;
+    write_png_chunk(out, b"IDAT", &zlib)?;
+    write_png_chunk(out, b"IEND", &[])
+}
+
+fn registers_json(machine: &Machine) -> String {
+    let cpu = &machine.cpu;
+    let list = |values: &mut dyn Iterator<Item = u16>| {
+        values
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    let status = match cpu.status {
+        Status::Running => "\"running\"".to_string(),
+        Status::Halt => "\"halt\"".to_string(),
+        Status::WaitingKeypress(_) => "\"waiting_keypress\"".to_string(),
+        Status::WaitingVblank => "\"waiting_vblank\"".to_string(),
+        Status::Faulted { pc, opcode } => {
+            format!("{{\"faulted\":{{\"pc\":{},\"opcode\":{}}}}}", pc, opcode)
+        }
+    };
+
+    format!(
+        "{{\"frames\":{},\"pc\":{},\"i\":{},\"sp\":{},\"delay\":{},\"sound\":{},\"v\":[{}],\"stack\":[{}],\"status\":{}}}",
+        machine.frames(),
+        cpu.pc,
+        cpu.i,
+        cpu.sp,
+        cpu.delay,
+        cpu.sound,
+        list(&mut cpu.v.iter().map(|&v| u16::from(v))),
+        list(&mut cpu.stack.iter().copied()),
+        status,
+    )
+}
+
+fn load(path: &str, map: MemoryMap) -> Result<MMU, String> {
+    let failed = |error: &dyn fmt::Display| format!("could not load {}: {}", path, error);
+    let mut mmu = MMU::new(map).map_err(|error| failed(&error))?;
+    mmu.load_game(path).map_err(|error| failed(&error))?;
+
+    Ok(mmu)
+}
+
+fn run(options: Options) -> Result<bool, String> {
+    let database = match &options.database {
+        _ if options.no_database => Database::default(),
+        Some(dir) => Database::from_dir(dir)
+            .map_err(|error| format!("could not read the database in {}: {}", dir, error))?,
+        None => Database::bundled(),
+    };
+
+    let mut xo_chip = options.rom_path.ends_with(".xo8");
+
+    let map = options.memory.unwrap_or(if xo_chip {
+        MemoryMap::xo_chip()
+    } else {
+        MemoryMap::chip8()
+    });
+    let mut mmu = load(&options.rom_path, map)?;
+
+    let info = database.find(&mmu).cloned();
+    if let Some(info) = &info {
+        xo_chip |= info.platform.as_deref() == Some("xochip");
+    }
+    if let (Some(info), None) = (&info, options.memory) {
+        // the rom may need more memory or to be loaded elsewhere
+        let mut rom_map = map;
+        if xo_chip {
+            rom_map = MemoryMap::xo_chip();
+        }
+        if let Some(start_address) = info.start_address {
+            rom_map = rom_map.with_load_address(start_address);
+        }
+        if rom_map != map {
+            mmu = load(&options.rom_path, rom_map)?;
+        }
+    }
+    if options.strict_memory {
+        mmu.set_protection(Protection::strict());
+    }
+
+    let quirks = options
+        .quirks
+        .or_else(|| info.as_ref().and_then(|info| info.quirks))
+        .unwrap_or(if xo_chip {
+            Quirks::xo_chip()
+        } else {
+            Quirks::cosmac_vip()
+        });
+    let instructions_per_second = options.instructions_per_second.or_else(|| {
+        let tick_rate = info.as_ref().and_then(|info| info.tick_rate)?;
+        Some(tick_rate.saturating_mul(TIMER_FREQUENCY))
+    });
+
+    let mut machine = Machine::new(CPU::new(mmu, quirks)).with_timing(options.timing);
+    if let Some(path) = &options.trace {
+        let file =
+            File::create(path).map_err(|error| format!("could not create {}: {}", path, error))?;
+        let tracer = TraceWriter::new(BufWriter::new(file), options.trace_format);
+        machine.cpu.set_tracer(Some(Box::new(tracer)));
+    }
+    if let Some(instructions_per_second) = instructions_per_second {
+        machine.set_instructions_per_second(instructions_per_second);
+    }
+
+    let mut next_key = 0;
+    let mut result = Ok(());
+    match options.budget {
+        Budget::Frames(frames) => {
+            while machine.frames() < frames && result.is_ok() {
+                apply_keys(&mut machine, &options.keys, &mut next_key);
+                result = machine.run_frame();
+            }
+        }
+        Budget::Cycles(cycles) => {
+            for _ in 0..cycles {
+                apply_keys(&mut machine, &options.keys, &mut next_key);
+                result = machine.step();
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    let faulted = result.is_err();
+    if let Err(error) = result {
+        eprintln!("CPU fault: {}", error);
+    }
+
+    let write_screen = |out: &mut dyn Write| match options.screen {
+        ScreenFormat::Ascii => write_ascii(out, &machine.cpu),
+        ScreenFormat::Pbm => write_pbm(out, &machine.cpu),
+        ScreenFormat::Png => write_png(out, &machine.cpu),
+    };
+
+    match &options.output {
+        Some(path) => {
+            let mut file = File::create(path)
+                .map_err(|error| format!("could not create {}: {}", path, error))?;
+            write_screen(&mut file)
+        }
+        None => write_screen(&mut io::stdout().lock()),
+    }
+    .map_err(|error| format!("could not write the screen: {}", error))?;
+
+    if options.registers {
+        println!("{}", registers_json(&machine));
+    }
+
+    Ok(!faulted)
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+
+    match run(options) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(2);
+        }
+    }
+}
@@ -1,27 +1,78 @@
 extern crate chip8;
 
-use chip8::cpu::CPU;
-use chip8::mmu::MMU;
-use std::env;
+use chip8::cpu::{Quirks, CPU};
+use chip8::database::Database;
+use chip8::machine::{Machine, TIMER_FREQUENCY};
+use chip8::mmu::{LoadError, MemoryMap, MMU};
+use chip8::rewind::DEFAULT_REWIND_FRAMES;
+use std::{env, fmt, process};
 
-fn main() {
-    let mut mmu: MMU = Default::default();
+fn load(path: &str, map: MemoryMap) -> Result<MMU, LoadError> {
+    let mut mmu = exit_on_error(path, MMU::new(map));
+    mmu.load_game(path)?;
+    Ok(mmu)
+}
 
+fn exit_on_error<T, E: fmt::Display>(path: &str, result: Result<T, E>) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("could not load {}: {}", path, error);
+        process::exit(1);
+    })
+}
+
+fn main() {
     // get the rom path from the first argument
-    if let Some(rom_path) = env::args().nth(1) {
-        println!("{}", rom_path);
-        mmu.load_game(rom_path).unwrap();
+    let rom_path = match env::args().nth(1) {
+        Some(rom_path) => rom_path,
+        None => panic!("You should pass the rom path as argument"),
+    };
+    println!("{}", rom_path);
+
+    // XO-CHIP roms need the extended memory and their own quirks
+    let (mut map, mut quirks) = if rom_path.ends_with(".xo8") {
+        (MemoryMap::xo_chip(), Quirks::xo_chip())
     } else {
-        panic!("You should pass the rom path as argument")
-    }
+        (MemoryMap::chip8(), Quirks::default())
+    };
+    let loaded = match load(&rom_path, map) {
+        // only XO-CHIP roms take more than 4KiB
+        Err(LoadError::TooLarge { .. }) if map != MemoryMap::xo_chip() => {
+            map = MemoryMap::xo_chip();
+            quirks = Quirks::xo_chip();
+            load(&rom_path, map)
+        }
+        result => result,
+    };
+    let mut mmu = exit_on_error(&rom_path, loaded);
 
-    let mut cpu: CPU = CPU::new(mmu);
+    // known roms get the settings they need
+    let database = Database::bundled();
+    let info = database.find(&mmu).cloned();
+    if let Some(info) = &info {
+        println!("{}", info.title);
 
-    #[cfg(feature = "sdl")]
-    chip8::frontend::SDL::new(&mut cpu).run();
+        if info.platform.as_deref() == Some("xochip") {
+            map = MemoryMap::xo_chip();
+            quirks = Quirks::xo_chip();
+        }
+        if let Some(start_address) = info.start_address {
+            map = map.with_load_address(start_address);
+        }
+        if map != mmu.map() {
+            mmu = exit_on_error(&rom_path, load(&rom_path, map));
+        }
+        quirks = info.quirks.unwrap_or(quirks);
+    }
+
+    let cpu: CPU = CPU::new(mmu, quirks);
+    let mut machine = Machine::new(cpu).with_rewind(DEFAULT_REWIND_FRAMES);
+    if let Some(tick_rate) = info.as_ref().and_then(|info| info.tick_rate) {
+        machine.set_instructions_per_second(tick_rate.saturating_mul(TIMER_FREQUENCY));
+    }
 
-    #[cfg(not(feature = "sdl"))]
-    loop {
-        cpu.cycle()
+    let mut sdl = chip8::frontend::SDL::new(&mut machine).with_state_path(&rom_path);
+    if let Some(info) = &info {
+        sdl = sdl.with_rom_info(info);
     }
+    sdl.run();
 }
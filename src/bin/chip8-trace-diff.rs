@@ -0,0 +1,68 @@
+extern crate chip8;
+
+use chip8::trace::{first_divergence, read_trace, Divergence, TraceRecord};
+
+use std::fs::File;
+use std::io::BufReader;
+use std::{env, process};
+
+const USAGE: &str = "Usage: chip8-trace-diff <TRACE A> <TRACE B>
+
+Compares two execution traces (text or JSON lines) and reports where they first
+diverge. Exits with 0 when the traces are identical and 1 when they differ.";
+
+fn read(path: &str) -> Result<Vec<TraceRecord>, String> {
+    let file = File::open(path).map_err(|error| format!("could not open {}: {}", path, error))?;
+    read_trace(BufReader::new(file)).map_err(|error| format!("{}: {}", path, error))
+}
+
+fn run(a_path: &str, b_path: &str) -> Result<bool, String> {
+    let (a, b) = (read(a_path)?, read(b_path)?);
+    let records = a.len();
+
+    match first_divergence(a, b) {
+        None => {
+            println!("traces are identical ({} records)", records);
+            Ok(true)
+        }
+        Some(Divergence::Record { index, a, b }) => {
+            println!("traces diverge at record {} (cycle {})", index, a.cycle);
+            println!("  a: {:03X}  {}", a.pc, a.instruction);
+            println!("  b: {:03X}  {}", b.pc, b.instruction);
+            for (field, a, b) in a.differences(&b) {
+                println!("  {}: {} != {}", field, a, b);
+            }
+            Ok(false)
+        }
+        Some(Divergence::Length { index, a_ended }) => {
+            let (ended, other) = if a_ended {
+                (a_path, b_path)
+            } else {
+                (b_path, a_path)
+            };
+            println!("{} ends after {} records, {} goes on", ended, index, other);
+            Ok(false)
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    if args.len() != 2 {
+        eprintln!("expected two traces\n\n{}", USAGE);
+        process::exit(2);
+    }
+
+    match run(&args[0], &args[1]) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! The memory and display a [`CPU`](crate::cpu::CPU) is wired to.
+//!
+//! [`MMU`] is the emulator's own bus and what the CPU uses by default. Other buses can
+//! wrap it, to log or mirror the accesses for instance, or stand in for it in tests.
+//! Save states, and so rewinding and [`Machine`](crate::machine::Machine), need the
+//! [`MMU`].
+
+use crate::mmu::{MemoryError, MMU};
+
+pub trait Bus {
+    /// Read a byte of data
+    fn rb(&mut self, offset: usize) -> Result<u8, MemoryError>;
+    /// Write a byte of data
+    fn wb(&mut self, offset: usize, byte: u8) -> Result<(), MemoryError>;
+    /// Read a byte that isn't a data access (instruction fetches, debuggers)
+    fn peek(&self, offset: usize) -> Result<u8, MemoryError>;
+
+    /// Read a big-endian word of data
+    fn rw(&mut self, offset: usize) -> Result<u16, MemoryError> {
+        Ok(u16::from(self.rb(offset)?) << 8 | u16::from(self.rb(offset + 1)?))
+    }
+    /// Write a big-endian word of data
+    fn ww(&mut self, offset: usize, word: u16) -> Result<(), MemoryError> {
+        self.wb(offset, (word >> 8) as u8)?;
+        self.wb(offset + 1, word as u8)
+    }
+    /// Read an instruction word
+    fn fetch(&self, offset: usize) -> Result<u16, MemoryError> {
+        Ok(u16::from(self.peek(offset)?) << 8 | u16::from(self.peek(offset + 1)?))
+    }
+
+    /// Width of the screen in the current resolution
+    fn width(&self) -> usize;
+    /// Height of the screen in the current resolution
+    fn height(&self) -> usize;
+    fn hires(&self) -> bool;
+    /// Switch between low and high resolution, clearing the screen
+    fn set_hires(&mut self, hires: bool);
+
+    /// The (XO-CHIP) bit planes affected by drawing, clearing and scrolling
+    fn planes(&self) -> u8;
+    fn select_planes(&mut self, planes: u8);
+
+    /// Bitmask of the planes lit on the pixel at (x, y)
+    fn rb_vram(&self, x: usize, y: usize) -> u8;
+    /// Clear the selected planes
+    fn clear_vram(&mut self);
+    fn scroll_down(&mut self, n: usize);
+    fn scroll_right(&mut self, n: usize);
+    fn scroll_left(&mut self, n: usize);
+    /// Draw `sprite`, `width` pixels wide, at (x, y), returning whether any lit pixel
+    /// was erased. See [`MMU::draw_sprite`].
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], width: usize, clip: bool) -> bool;
+}
+
+impl Bus for MMU {
+    fn rb(&mut self, offset: usize) -> Result<u8, MemoryError> {
+        MMU::rb(self, offset)
+    }
+    fn wb(&mut self, offset: usize, byte: u8) -> Result<(), MemoryError> {
+        MMU::wb(self, offset, byte)
+    }
+    fn peek(&self, offset: usize) -> Result<u8, MemoryError> {
+        MMU::peek(self, offset)
+    }
+
+    fn width(&self) -> usize {
+        MMU::width(self)
+    }
+    fn height(&self) -> usize {
+        MMU::height(self)
+    }
+    fn hires(&self) -> bool {
+        MMU::hires(self)
+    }
+    fn set_hires(&mut self, hires: bool) {
+        MMU::set_hires(self, hires)
+    }
+
+    fn planes(&self) -> u8 {
+        MMU::planes(self)
+    }
+    fn select_planes(&mut self, planes: u8) {
+        MMU::select_planes(self, planes)
+    }
+
+    fn rb_vram(&self, x: usize, y: usize) -> u8 {
+        MMU::rb_vram(self, x, y)
+    }
+    fn clear_vram(&mut self) {
+        MMU::clear_vram(self)
+    }
+    fn scroll_down(&mut self, n: usize) {
+        MMU::scroll_down(self, n)
+    }
+    fn scroll_right(&mut self, n: usize) {
+        MMU::scroll_right(self, n)
+    }
+    fn scroll_left(&mut self, n: usize) {
+        MMU::scroll_left(self, n)
+    }
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], width: usize, clip: bool) -> bool {
+        MMU::draw_sprite(self, x, y, sprite, width, clip)
+    }
+}
@@ -0,0 +1,61 @@
+use std::{error, fmt};
+
+use super::instruction::AddressingMode;
+use crate::mmu::{MemoryError, Region};
+
+/// Errors raised while decoding or executing an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuError {
+    /// The word doesn't decode to any known instruction
+    UnknownOpcode(u16),
+    /// The instruction was given operands it doesn't accept
+    InvalidAddressingMode(AddressingMode),
+    /// A register index outside of V0..=VF
+    InvalidRegister(usize),
+    /// CALL with all the 16 stack levels in use
+    StackOverflow,
+    /// RET with an empty stack
+    StackUnderflow,
+    /// Memory access outside of the addressable range
+    AddressOutOfRange(usize),
+    /// A write to memory whose [`WritePolicy`](crate::mmu::WritePolicy) faults
+    ProtectedWrite { offset: usize, region: Region },
+    /// SYS executed while the [`SysPolicy`](super::SysPolicy) refuses it
+    UnsupportedSys(u16),
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode(opcode) => write!(f, "unknown opcode 0x{:04x}", opcode),
+            CpuError::InvalidAddressingMode(mode) => {
+                write!(f, "invalid addressing mode {:x?}", mode)
+            }
+            CpuError::InvalidRegister(x) => write!(f, "invalid register V{:x}", x),
+            CpuError::StackOverflow => write!(f, "stack overflow"),
+            CpuError::StackUnderflow => write!(f, "stack underflow"),
+            CpuError::AddressOutOfRange(offset) => {
+                write!(f, "address out of range: 0x{:04x}", offset)
+            }
+            CpuError::ProtectedWrite { offset, region } => {
+                write!(f, "write to protected {} memory: 0x{:04x}", region, offset)
+            }
+            CpuError::UnsupportedSys(addr) => {
+                write!(f, "SYS instruction not supported: 0x{:03x}", addr)
+            }
+        }
+    }
+}
+
+impl error::Error for CpuError {}
+
+impl From<MemoryError> for CpuError {
+    fn from(error: MemoryError) -> Self {
+        match error {
+            MemoryError::OutOfRange(offset) => CpuError::AddressOutOfRange(offset),
+            MemoryError::Protected { offset, region } => {
+                CpuError::ProtectedWrite { offset, region }
+            }
+        }
+    }
+}
@@ -1,6 +1,11 @@
-use super::Status;
+use std::convert::TryFrom;
+use std::fmt;
 
-#[derive(Debug, Clone, Copy)]
+use super::{CpuError, Status, SysPolicy};
+use crate::bus::Bus;
+use crate::mmu::BIG_FONT_OFFSET;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Opcode {
     CLS,
     RET,
@@ -22,11 +27,25 @@ pub enum Opcode {
     DRW,
     SKP,
     SKNP,
+
+    // SUPER-CHIP
+    SCD,
+    SCR,
+    SCL,
+    EXIT,
+    LOW,
+    HIGH,
+
+    // XO-CHIP
+    PLANE,
+    AUDIO,
+    PITCH,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddressingMode {
     Implicit,
+    Nibble(u8),
     Addr(u16),
 
     Vx(usize),
@@ -41,20 +60,88 @@ pub enum AddressingMode {
     V0Addr(u16),
 
     IAddr(u16),
+    ILongAddr(u16),
     IVx(usize),
     FVx(usize),
     BVx(usize),
     MemVx(usize),
+    MemVxVy(usize, usize),
+    VxVyMem(usize, usize),
 
     DTVx(usize),
     STVx(usize),
+
+    HFVx(usize),
+    RVx(usize),
+    VxR(usize),
+}
+
+impl AddressingMode {
+    /// The indices of the Vx/Vy registers this addressing mode refers to
+    pub fn registers(self) -> impl Iterator<Item = usize> {
+        let (x, y) = match self {
+            AddressingMode::Implicit
+            | AddressingMode::Nibble(_)
+            | AddressingMode::Addr(_)
+            | AddressingMode::V0Addr(_)
+            | AddressingMode::IAddr(_)
+            | AddressingMode::ILongAddr(_) => (None, None),
+
+            AddressingMode::Vx(x)
+            | AddressingMode::VxImediate(x, _)
+            | AddressingMode::VxDT(x)
+            | AddressingMode::VxKey(x)
+            | AddressingMode::VxMem(x)
+            | AddressingMode::IVx(x)
+            | AddressingMode::FVx(x)
+            | AddressingMode::BVx(x)
+            | AddressingMode::MemVx(x)
+            | AddressingMode::DTVx(x)
+            | AddressingMode::STVx(x)
+            | AddressingMode::HFVx(x)
+            | AddressingMode::RVx(x)
+            | AddressingMode::VxR(x) => (Some(x), None),
+
+            AddressingMode::VxVy(x, y)
+            | AddressingMode::VxVyImediate(x, y, _)
+            | AddressingMode::MemVxVy(x, y)
+            | AddressingMode::VxVyMem(x, y) => (Some(x), Some(y)),
+        };
+
+        x.into_iter().chain(y)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Instruction(pub Opcode, pub AddressingMode);
 
-impl From<u16> for Instruction {
-    fn from(opcode: u16) -> Self {
+/// First word of the XO-CHIP `F000 NNNN` long I load, the only 4 bytes instruction
+pub const LONG_LOAD: u16 = 0xF000;
+
+impl Instruction {
+    /// Decode an instruction from its first word and the word following it,
+    /// which is only used by the XO-CHIP `F000 NNNN` long I load
+    pub fn decode(opcode: u16, next: u16) -> Result<Self, CpuError> {
+        if opcode == LONG_LOAD {
+            Ok(Instruction(Opcode::LD, AddressingMode::ILongAddr(next)))
+        } else {
+            Instruction::try_from(opcode)
+        }
+    }
+
+    /// Size of the encoded instruction in bytes
+    pub fn size(&self) -> u16 {
+        match self.1 {
+            AddressingMode::ILongAddr(_) => 4,
+            _ => 2,
+        }
+    }
+}
+
+impl TryFrom<u16> for Instruction {
+    type Error = CpuError;
+
+    fn try_from(opcode: u16) -> Result<Self, Self::Error> {
         let opcode_nibbles = (
             ((opcode >> 0xC) & 0xF) as u8,
             ((opcode >> 0x8) & 0xF) as usize,
@@ -62,10 +149,18 @@ impl From<u16> for Instruction {
             (opcode & 0xF) as u8,
         );
 
-        match opcode_nibbles {
+        let instruction = match opcode_nibbles {
             (0x0, 0x0, 0xE, 0x0) => Instruction(Opcode::CLS, AddressingMode::Implicit),
 
             (0x0, 0x0, 0xE, 0xE) => Instruction(Opcode::RET, AddressingMode::Implicit),
+
+            (0x0, 0x0, 0xC, n) => Instruction(Opcode::SCD, AddressingMode::Nibble(n)),
+            (0x0, 0x0, 0xF, 0xB) => Instruction(Opcode::SCR, AddressingMode::Implicit),
+            (0x0, 0x0, 0xF, 0xC) => Instruction(Opcode::SCL, AddressingMode::Implicit),
+            (0x0, 0x0, 0xF, 0xD) => Instruction(Opcode::EXIT, AddressingMode::Implicit),
+            (0x0, 0x0, 0xF, 0xE) => Instruction(Opcode::LOW, AddressingMode::Implicit),
+            (0x0, 0x0, 0xF, 0xF) => Instruction(Opcode::HIGH, AddressingMode::Implicit),
+
             (0x0, _, _, _) => Instruction(Opcode::SYS, AddressingMode::Addr(opcode & 0xFFF)),
             (0x1, _, _, _) => Instruction(Opcode::JP, AddressingMode::Addr(opcode & 0xFFF)),
             (0x2, _, _, _) => Instruction(Opcode::CALL, AddressingMode::Addr(opcode & 0xFFF)),
@@ -79,6 +174,8 @@ impl From<u16> for Instruction {
                 AddressingMode::VxImediate(x, (opcode & 0xFF) as u8),
             ),
             (0x5, x, y, 0) => Instruction(Opcode::SE, AddressingMode::VxVy(x, y)),
+            (0x5, x, y, 2) => Instruction(Opcode::LD, AddressingMode::MemVxVy(x, y)),
+            (0x5, x, y, 3) => Instruction(Opcode::LD, AddressingMode::VxVyMem(x, y)),
             (0x6, x, _, _) => Instruction(
                 Opcode::LD,
                 AddressingMode::VxImediate(x, (opcode & 0xFF) as u8),
@@ -113,47 +210,271 @@ impl From<u16> for Instruction {
             (0xE, x, 0x9, 0xE) => Instruction(Opcode::SKP, AddressingMode::Vx(x)),
             (0xE, x, 0xA, 0x1) => Instruction(Opcode::SKNP, AddressingMode::Vx(x)),
 
+            (0xF, 0x0, 0x0, 0x2) => Instruction(Opcode::AUDIO, AddressingMode::Implicit),
+            (0xF, n, 0x0, 0x1) => Instruction(Opcode::PLANE, AddressingMode::Nibble(n as u8)),
             (0xF, x, 0x0, 0x7) => Instruction(Opcode::LD, AddressingMode::VxDT(x)),
             (0xF, x, 0x0, 0xA) => Instruction(Opcode::LD, AddressingMode::VxKey(x)),
             (0xF, x, 0x1, 0x5) => Instruction(Opcode::LD, AddressingMode::DTVx(x)),
             (0xF, x, 0x1, 0x8) => Instruction(Opcode::LD, AddressingMode::STVx(x)),
             (0xF, x, 0x1, 0xE) => Instruction(Opcode::ADD, AddressingMode::IVx(x)),
             (0xF, x, 0x2, 0x9) => Instruction(Opcode::LD, AddressingMode::FVx(x)),
+            (0xF, x, 0x3, 0x0) => Instruction(Opcode::LD, AddressingMode::HFVx(x)),
             (0xF, x, 0x3, 0x3) => Instruction(Opcode::LD, AddressingMode::BVx(x)),
+            (0xF, x, 0x3, 0xA) => Instruction(Opcode::PITCH, AddressingMode::Vx(x)),
             (0xF, x, 0x5, 0x5) => Instruction(Opcode::LD, AddressingMode::MemVx(x)),
             (0xF, x, 0x6, 0x5) => Instruction(Opcode::LD, AddressingMode::VxMem(x)),
+            (0xF, x, 0x7, 0x5) => Instruction(Opcode::LD, AddressingMode::RVx(x)),
+            (0xF, x, 0x8, 0x5) => Instruction(Opcode::LD, AddressingMode::VxR(x)),
+
+            _ => return Err(CpuError::UnknownOpcode(opcode)),
+        };
+
+        Ok(instruction)
+    }
+}
+
+impl Instruction {
+    /// Encode the instruction back into its first word, the inverse of
+    /// [`Instruction::decode`]. The XO-CHIP long I load is the only instruction that
+    /// needs the word after it, see [`Instruction::to_bytes`].
+    ///
+    /// Fails when the addressing mode doesn't go with the opcode, or when an operand
+    /// doesn't fit in its field (registers above VF, addresses above 0xFFF, nibbles
+    /// above 0xF, or a SYS address that would encode another instruction).
+    pub fn encode(self) -> Result<u16, CpuError> {
+        use AddressingMode::*;
+        use Opcode::*;
+
+        if let Some(x) = self.1.registers().find(|&x| x > 0xF) {
+            return Err(CpuError::InvalidRegister(x));
+        }
+        match self.1 {
+            Addr(addr) | V0Addr(addr) | IAddr(addr) if addr > 0xFFF => {
+                return Err(CpuError::AddressOutOfRange(usize::from(addr)))
+            }
+            _ => {}
+        }
+
+        let xy = |x: usize, y: usize| (x as u16) << 8 | (y as u16) << 4;
+        let xkk = |x: usize, kk: u8| (x as u16) << 8 | u16::from(kk);
+        let x = |x: usize| (x as u16) << 8;
+
+        let word = match (self.0, self.1) {
+            (CLS, Implicit) => 0x00E0,
+            (RET, Implicit) => 0x00EE,
+            (SCD, Nibble(n)) => 0x00C0 | u16::from(n),
+            (SCR, Implicit) => 0x00FB,
+            (SCL, Implicit) => 0x00FC,
+            (EXIT, Implicit) => 0x00FD,
+            (LOW, Implicit) => 0x00FE,
+            (HIGH, Implicit) => 0x00FF,
+            (SYS, Addr(addr)) => addr,
+            (JP, Addr(addr)) => 0x1000 | addr,
+            (CALL, Addr(addr)) => 0x2000 | addr,
+            (SE, VxImediate(vx, kk)) => 0x3000 | xkk(vx, kk),
+            (SNE, VxImediate(vx, kk)) => 0x4000 | xkk(vx, kk),
+            (SE, VxVy(vx, vy)) => 0x5000 | xy(vx, vy),
+            (LD, MemVxVy(vx, vy)) => 0x5002 | xy(vx, vy),
+            (LD, VxVyMem(vx, vy)) => 0x5003 | xy(vx, vy),
+            (LD, VxImediate(vx, kk)) => 0x6000 | xkk(vx, kk),
+            (ADD, VxImediate(vx, kk)) => 0x7000 | xkk(vx, kk),
+            (LD, VxVy(vx, vy)) => 0x8000 | xy(vx, vy),
+            (OR, VxVy(vx, vy)) => 0x8001 | xy(vx, vy),
+            (AND, VxVy(vx, vy)) => 0x8002 | xy(vx, vy),
+            (XOR, VxVy(vx, vy)) => 0x8003 | xy(vx, vy),
+            (ADD, VxVy(vx, vy)) => 0x8004 | xy(vx, vy),
+            (SUB, VxVy(vx, vy)) => 0x8005 | xy(vx, vy),
+            (SHR, VxVy(vx, vy)) => 0x8006 | xy(vx, vy),
+            (SUBN, VxVy(vx, vy)) => 0x8007 | xy(vx, vy),
+            (SHL, VxVy(vx, vy)) => 0x800E | xy(vx, vy),
+            (SNE, VxVy(vx, vy)) => 0x9000 | xy(vx, vy),
+            (LD, IAddr(addr)) => 0xA000 | addr,
+            (LD, ILongAddr(_)) => LONG_LOAD,
+            (JP, V0Addr(addr)) => 0xB000 | addr,
+            (RND, VxImediate(vx, kk)) => 0xC000 | xkk(vx, kk),
+            (DRW, VxVyImediate(vx, vy, n)) => 0xD000 | xy(vx, vy) | u16::from(n),
+            (SKP, Vx(vx)) => 0xE09E | x(vx),
+            (SKNP, Vx(vx)) => 0xE0A1 | x(vx),
+            (AUDIO, Implicit) => 0xF002,
+            (PLANE, Nibble(n)) => 0xF001 | u16::from(n) << 8,
+            (LD, VxDT(vx)) => 0xF007 | x(vx),
+            (LD, VxKey(vx)) => 0xF00A | x(vx),
+            (LD, DTVx(vx)) => 0xF015 | x(vx),
+            (LD, STVx(vx)) => 0xF018 | x(vx),
+            (ADD, IVx(vx)) => 0xF01E | x(vx),
+            (LD, FVx(vx)) => 0xF029 | x(vx),
+            (LD, HFVx(vx)) => 0xF030 | x(vx),
+            (LD, BVx(vx)) => 0xF033 | x(vx),
+            (PITCH, Vx(vx)) => 0xF03A | x(vx),
+            (LD, MemVx(vx)) => 0xF055 | x(vx),
+            (LD, VxMem(vx)) => 0xF065 | x(vx),
+            (LD, RVx(vx)) => 0xF075 | x(vx),
+            (LD, VxR(vx)) => 0xF085 | x(vx),
+            _ => return Err(CpuError::InvalidAddressingMode(self.1)),
+        };
+
+        // whatever didn't fit in its field decodes to something else
+        if Instruction::decode(word, self.long_address()) != Ok(self) {
+            return Err(CpuError::InvalidAddressingMode(self.1));
+        }
+
+        Ok(word)
+    }
+
+    // the word following a long I load
+    fn long_address(self) -> u16 {
+        match self.1 {
+            AddressingMode::ILongAddr(addr) => addr,
+            _ => 0,
+        }
+    }
+
+    /// The whole encoded instruction, [`Instruction::size`] bytes long
+    pub fn to_bytes(self) -> Result<Vec<u8>, CpuError> {
+        let mut bytes = self.encode()?.to_be_bytes().to_vec();
+        if self.size() == 4 {
+            bytes.extend_from_slice(&self.long_address().to_be_bytes());
+        }
+
+        Ok(bytes)
+    }
+}
+
+impl TryFrom<Instruction> for u16 {
+    type Error = CpuError;
+
+    fn try_from(instruction: Instruction) -> Result<Self, Self::Error> {
+        instruction.encode()
+    }
+}
+
+// registers from `x` to `y`, in this order, even if `x` is greater than `y`
+fn register_range(x: usize, y: usize) -> impl Iterator<Item = usize> {
+    let count = x.max(y) - x.min(y) + 1;
+    (0..count).map(move |n| if x <= y { x + n } else { x - n })
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the variants are named after their mnemonics
+        write!(f, "{:?}", self)
+    }
+}
 
-            _ => unreachable!(),
+impl fmt::Display for AddressingMode {
+    /// The operands in Cowgod's syntax: registers as `Vx`, bytes and addresses in
+    /// hex (`#2A`, `#208`) and nibbles in decimal
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            AddressingMode::Implicit => Ok(()),
+            AddressingMode::Nibble(n) => write!(f, "{}", n),
+            AddressingMode::Addr(addr) => write!(f, "#{:03X}", addr),
+
+            AddressingMode::Vx(x) => write!(f, "V{:X}", x),
+            AddressingMode::VxImediate(x, kk) => write!(f, "V{:X}, #{:02X}", x, kk),
+            AddressingMode::VxDT(x) => write!(f, "V{:X}, DT", x),
+            AddressingMode::VxKey(x) => write!(f, "V{:X}, K", x),
+            AddressingMode::VxMem(x) => write!(f, "V{:X}, [I]", x),
+
+            AddressingMode::VxVy(x, y) => write!(f, "V{:X}, V{:X}", x, y),
+            AddressingMode::VxVyImediate(x, y, n) => write!(f, "V{:X}, V{:X}, {}", x, y, n),
+
+            AddressingMode::V0Addr(addr) => write!(f, "V0, #{:03X}", addr),
+
+            AddressingMode::IAddr(addr) => write!(f, "I, #{:03X}", addr),
+            AddressingMode::ILongAddr(addr) => write!(f, "I, LONG #{:04X}", addr),
+            AddressingMode::IVx(x) => write!(f, "I, V{:X}", x),
+            AddressingMode::FVx(x) => write!(f, "F, V{:X}", x),
+            AddressingMode::BVx(x) => write!(f, "B, V{:X}", x),
+            AddressingMode::MemVx(x) => write!(f, "[I], V{:X}", x),
+            AddressingMode::MemVxVy(x, y) => write!(f, "[I], V{:X}-V{:X}", x, y),
+            AddressingMode::VxVyMem(x, y) => write!(f, "V{:X}-V{:X}, [I]", x, y),
+
+            AddressingMode::DTVx(x) => write!(f, "DT, V{:X}", x),
+            AddressingMode::STVx(x) => write!(f, "ST, V{:X}", x),
+
+            AddressingMode::HFVx(x) => write!(f, "HF, V{:X}", x),
+            AddressingMode::RVx(x) => write!(f, "R, V{:X}", x),
+            AddressingMode::VxR(x) => write!(f, "V{:X}, R", x),
         }
     }
 }
 
-// TODO: debug support for instructions
-// impl Display for Instruction {  }
+impl fmt::Display for Instruction {
+    /// Cowgod's mnemonics, like `LD V3, #2A` or `DRW V0, V1, 5`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            AddressingMode::Implicit => write!(f, "{}", self.0),
+            addressing_mode => write!(f, "{} {}", self.0, addressing_mode),
+        }
+    }
+}
 
-impl super::CPU {
+impl<B: Bus> super::CPU<B> {
     /// CLS: clear the screen
-    pub fn exec_cls(&mut self) {
-        for b in self.bus.borrow_mut().vram.iter_mut() {
-            *b = 0;
+    pub fn exec_cls(&mut self) -> Result<(), CpuError> {
+        self.bus.clear_vram();
+        Ok(())
+    }
+
+    /// SCD: scroll the screen down `n` pixels
+    pub fn exec_scd(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
+        if let AddressingMode::Nibble(n) = addressing_mode {
+            self.bus.scroll_down(usize::from(n));
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
+    /// SCR: scroll the screen right 4 pixels
+    pub fn exec_scr(&mut self) -> Result<(), CpuError> {
+        self.bus.scroll_right(4);
+        Ok(())
+    }
+    /// SCL: scroll the screen left 4 pixels
+    pub fn exec_scl(&mut self) -> Result<(), CpuError> {
+        self.bus.scroll_left(4);
+        Ok(())
+    }
+
+    /// EXIT: stop the interpreter
+    pub fn exec_exit(&mut self) -> Result<(), CpuError> {
+        self.status = Status::Halt;
+        Ok(())
+    }
+
+    /// LOW: switch to the 64x32 low resolution mode
+    pub fn exec_low(&mut self) -> Result<(), CpuError> {
+        self.bus.set_hires(false);
+        Ok(())
+    }
+    /// HIGH: switch to the 128x64 high resolution mode
+    pub fn exec_high(&mut self) -> Result<(), CpuError> {
+        self.bus.set_hires(true);
+        Ok(())
+    }
 
     /// JP: jump to the given address
-    pub fn exec_jp(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_jp(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         match addressing_mode {
             AddressingMode::Addr(addr) => {
                 self.pc = addr;
             }
             AddressingMode::V0Addr(addr) => {
-                self.pc = u16::from(self.v[0]) + addr;
+                let x = if self.quirks.jump_with_vx {
+                    usize::from(addr >> 8)
+                } else {
+                    0
+                };
+                self.pc = u16::from(self.v[x]) + addr;
             }
-            _ => unreachable!(),
+            _ => return Err(CpuError::InvalidAddressingMode(addressing_mode)),
         }
+        Ok(())
     }
 
     /// LD: Load the content of a register/memory location into another
-    pub fn exec_ld(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_ld(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         match addressing_mode {
             AddressingMode::VxImediate(x, kk) => {
                 self.v[x] = kk;
@@ -161,73 +482,121 @@ impl super::CPU {
             AddressingMode::VxVy(x, y) => {
                 self.v[x] = self.v[y];
             }
-            AddressingMode::IAddr(addr) => {
+            AddressingMode::IAddr(addr) | AddressingMode::ILongAddr(addr) => {
                 self.i = addr;
             }
             AddressingMode::VxDT(x) => {
                 self.v[x] = self.delay;
             }
-            AddressingMode::VxKey(x) => self.status = Status::WaitingKeypress(x),
+            AddressingMode::VxKey(x) => {
+                // only keys released from now on count
+                self.keypad.clear_released();
+                self.status = Status::WaitingKeypress(x);
+            }
             AddressingMode::DTVx(x) => {
                 self.delay = self.v[x];
             }
             AddressingMode::STVx(x) => {
-                self.sound = self.v[x];
+                self.set_sound(self.v[x]);
             }
             AddressingMode::FVx(x) => {
-                self.i = u16::from(self.v[x]) * 5;
+                self.i = u16::from(self.v[x] & 0xF) * 5;
+            }
+            AddressingMode::HFVx(x) => {
+                self.i = (BIG_FONT_OFFSET + usize::from(self.v[x] & 0xF) * 10) as u16;
+            }
+            AddressingMode::MemVxVy(x, y) => {
+                for (n, rx) in register_range(x, y).enumerate() {
+                    let offset = usize::from(self.i) + n;
+                    self.bus.wb(offset, self.v[rx])?;
+                }
+            }
+            AddressingMode::VxVyMem(x, y) => {
+                for (n, rx) in register_range(x, y).enumerate() {
+                    let offset = usize::from(self.i) + n;
+                    self.v[rx] = self.bus.rb(offset)?;
+                }
+            }
+            AddressingMode::RVx(x) => {
+                let flags = self.rpl.get_mut(..=x).ok_or(CpuError::InvalidRegister(x))?;
+                flags.copy_from_slice(&self.v[..=x]);
+            }
+            AddressingMode::VxR(x) => {
+                let flags = self.rpl.get(..=x).ok_or(CpuError::InvalidRegister(x))?;
+                self.v[..=x].copy_from_slice(flags);
             }
             AddressingMode::BVx(x) => {
                 let i = usize::from(self.i);
                 let value = self.v[x];
 
-                self.bus.borrow_mut().wb(i, value / 100);
-                self.bus.borrow_mut().wb(i + 1, (value % 100) / 10);
-                self.bus.borrow_mut().wb(i + 2, value % 10);
+                let bus = &mut self.bus;
+                bus.wb(i, value / 100)?;
+                bus.wb(i + 1, (value % 100) / 10)?;
+                bus.wb(i + 2, value % 10)?;
             }
             AddressingMode::MemVx(x) => {
                 for rx in 0..=x {
                     let offset = usize::from(self.i) + rx;
-                    self.bus.borrow_mut().wb(offset, self.v[rx]);
+                    self.bus.wb(offset, self.v[rx])?;
                 }
+                self.increment_i(x);
             }
             AddressingMode::VxMem(x) => {
                 for rx in 0..=x {
                     let offset = usize::from(self.i) + rx;
-                    self.v[rx] = self.bus.borrow_mut().rb(offset);
+                    self.v[rx] = self.bus.rb(offset)?;
                 }
+                self.increment_i(x);
             }
-            _ => unreachable!(),
+            _ => return Err(CpuError::InvalidAddressingMode(addressing_mode)),
+        }
+        Ok(())
+    }
+
+    // move I after LD [I], Vx and LD Vx, [I]
+    fn increment_i(&mut self, x: usize) {
+        if self.quirks.load_store_increments_i {
+            let past = if self.quirks.increment_i_by_x { 0 } else { 1 };
+            self.i = self.i.wrapping_add(x as u16 + past);
         }
     }
 
     /// DRW: update the VRAM (i.e. draw the screen)
-    pub fn exec_drw(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_drw(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         if let AddressingMode::VxVyImediate(x, y, n) = addressing_mode {
-            let (x, y) = (usize::from(self.v[x]), usize::from(self.v[y]));
-
-            self.v[0xF] = 0;
+            let planes = self.bus.planes();
+
+            // DXY0 draws a 16x16 sprite (SUPER-CHIP)
+            let (sprite_width, sprite_height) = if n == 0 {
+                (16, 16)
+            } else {
+                (8, usize::from(n))
+            };
+
+            // with both XO-CHIP planes selected, the sprite for the second plane
+            // follows the one for the first
+            let size = sprite_height * sprite_width / 8 * planes.count_ones() as usize;
+            let sprite = (0..size)
+                .map(|n| self.bus.rb(usize::from(self.i) + n))
+                .collect::<Result<Vec<u8>, _>>()?;
 
-            for row in 0..usize::from(n) {
-                let sprite_data = self.bus.borrow_mut().rb(usize::from(self.i) + row);
+            let (x, y) = (usize::from(self.v[x]), usize::from(self.v[y]));
+            let collision =
+                self.bus
+                    .draw_sprite(x, y, &sprite, sprite_width, self.quirks.clip_sprites);
+            self.v[0xF] = u8::from(collision);
 
-                for bit in 0..8 {
-                    if (sprite_data & (0x80 >> bit)) > 0 {
-                        if self
-                            .bus
-                            .borrow_mut()
-                            .wb_vram((x + bit) % 64, (y + row) % 32, 1)
-                        {
-                            self.v[0xF] = 1;
-                        }
-                    }
-                }
+            if self.quirks.display_wait {
+                self.status = Status::WaitingVblank;
             }
-        } else {unreachable!()}
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
+        }
     }
 
     /// ADD: add the content of a register with a value
-    pub fn exec_add(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_add(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         match addressing_mode {
             AddressingMode::VxImediate(x, kk) => {
                 self.v[x] = self.v[x].wrapping_add(kk);
@@ -241,132 +610,273 @@ impl super::CPU {
             AddressingMode::IVx(x) => {
                 self.i = self.i.wrapping_add(u16::from(self.v[x]));
             }
-            _ => unreachable!(),
+            _ => return Err(CpuError::InvalidAddressingMode(addressing_mode)),
         }
+        Ok(())
     }
     /// SUB: subract the content of a register with a value
-    pub fn exec_sub(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_sub(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
+        if let AddressingMode::VxVy(x, y) = addressing_mode {
+            let (result, did_borrow) = self.v[x].overflowing_sub(self.v[y]);
+
+            // VF is set to NOT borrow
+            self.v[x] = result;
+            self.v[0xF] = u8::from(!did_borrow);
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
+        }
+    }
+    /// SUBN: subtract the content of a register from `vy`, storing it in `vx`
+    pub fn exec_subn(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         if let AddressingMode::VxVy(x, y) = addressing_mode {
-            let (result, did_overflow) = self.v[x].overflowing_sub(self.v[y]);
+            let (result, did_borrow) = self.v[y].overflowing_sub(self.v[x]);
 
+            // VF is set to NOT borrow
             self.v[x] = result;
-            self.v[0xF] = u8::from(did_overflow);
+            self.v[0xF] = u8::from(!did_borrow);
+            Ok(())
         } else {
-            unreachable!()
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
     /// OR: bitwise-or the content of a register with a value
-    pub fn exec_or(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_or(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         if let AddressingMode::VxVy(x, y) = addressing_mode {
-            self.v[x] = self.v[x] | self.v[y];
+            self.v[x] |= self.v[y];
+            if self.quirks.vf_reset {
+                self.v[0xF] = 0;
+            }
+            Ok(())
         } else {
-            unreachable!()
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
     /// AND: bitwise-and the content of a register with a value
-    pub fn exec_and(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_and(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         if let AddressingMode::VxVy(x, y) = addressing_mode {
-            self.v[x] = self.v[x] & self.v[y];
+            self.v[x] &= self.v[y];
+            if self.quirks.vf_reset {
+                self.v[0xF] = 0;
+            }
+            Ok(())
         } else {
-            unreachable!()
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
     /// XOR: bitwise-xor the content of a register with a value
-    pub fn exec_xor(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_xor(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         if let AddressingMode::VxVy(x, y) = addressing_mode {
-            self.v[x] = self.v[x] ^ self.v[y];
+            self.v[x] ^= self.v[y];
+            if self.quirks.vf_reset {
+                self.v[0xF] = 0;
+            }
+            Ok(())
         } else {
-            unreachable!()
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
     /// SHL: shift left the bits of a register
-    pub fn exec_shl(&mut self, addressing_mode: AddressingMode) {
-        if let AddressingMode::VxVy(x, _) = addressing_mode {
-            self.v[0xf] = self.v[x] & 0x80;
-            self.v[x] <<= 1;
+    pub fn exec_shl(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
+        if let AddressingMode::VxVy(x, y) = addressing_mode {
+            let value = if self.quirks.shift_uses_vy {
+                self.v[y]
+            } else {
+                self.v[x]
+            };
+
+            self.v[x] = value << 1;
+            self.v[0xf] = value >> 7;
+            Ok(())
         } else {
-            unreachable!()
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
     /// SHR: shift right the bits of a register
-    pub fn exec_shr(&mut self, addressing_mode: AddressingMode) {
-        if let AddressingMode::VxVy(x, _) = addressing_mode {
-            self.v[0xf] = self.v[x] & 0x01;
-            self.v[x] >>= 1;
+    pub fn exec_shr(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
+        if let AddressingMode::VxVy(x, y) = addressing_mode {
+            let value = if self.quirks.shift_uses_vy {
+                self.v[y]
+            } else {
+                self.v[x]
+            };
+
+            self.v[x] = value >> 1;
+            self.v[0xf] = value & 0x01;
+            Ok(())
         } else {
-            unreachable!()
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
 
     /// SE: Skip the next instruction if `vx` is equal value
-    pub fn exec_se(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_se(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         let (x, byte) = match addressing_mode {
             AddressingMode::VxImediate(x, kk) => (x, kk),
             AddressingMode::VxVy(x, y) => (x, self.v[y]),
-            _ => unreachable!(),
+            _ => return Err(CpuError::InvalidAddressingMode(addressing_mode)),
         };
 
         if self.v[x] == byte {
-            self.pc += 2;
+            self.skip_next()?;
         }
+        Ok(())
     }
     /// SNE: Skip the next instruction if `vx` is not equal value
-    pub fn exec_sne(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_sne(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         let (x, byte) = match addressing_mode {
             AddressingMode::VxImediate(x, kk) => (x, kk),
             AddressingMode::VxVy(x, y) => (x, self.v[y]),
-            _ => unreachable!(),
+            _ => return Err(CpuError::InvalidAddressingMode(addressing_mode)),
         };
 
         if self.v[x] != byte {
-            self.pc += 2;
+            self.skip_next()?;
         }
+        Ok(())
     }
 
     /// CALL: Call the subroutine at `addr`
-    pub fn exec_call(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_call(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         if let AddressingMode::Addr(addr) = addressing_mode {
-            self.push_stack(self.pc);
+            self.push_stack(self.pc)?;
             self.pc = addr;
+            Ok(())
         } else {
-            unreachable!()
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
     /// RET: Return from a subroutine
-    pub fn exec_ret(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_ret(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         if let AddressingMode::Implicit = addressing_mode {
-            self.pc = self.pop_stack();
+            self.pc = self.pop_stack()?;
+            Ok(())
         } else {
-            unreachable!()
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
+        }
+    }
+
+    /// SYS: Jump to a machine code routine at `addr`, handled by the [`SysPolicy`]
+    pub fn exec_sys(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
+        if let AddressingMode::Addr(addr) = addressing_mode {
+            match self.sys_policy {
+                SysPolicy::Ignore => {}
+                SysPolicy::Trap(_) => {
+                    // take the callback out so it can borrow the cpu mutably
+                    let mut policy = std::mem::replace(&mut self.sys_policy, SysPolicy::Ignore);
+                    if let SysPolicy::Trap(ref mut callback) = policy {
+                        callback(self, addr);
+                    }
+                    self.sys_policy = policy;
+                }
+                SysPolicy::Error => return Err(CpuError::UnsupportedSys(addr)),
+            }
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
+        }
+    }
+
+    /// PLANE: select the bit planes used by the display instructions
+    pub fn exec_plane(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
+        if let AddressingMode::Nibble(n) = addressing_mode {
+            self.bus.select_planes(n);
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
+        }
+    }
+    /// AUDIO: load the 16 bytes audio pattern at I into the audio buffer
+    pub fn exec_audio(&mut self) -> Result<(), CpuError> {
+        for n in 0..self.audio_pattern.len() {
+            self.audio_pattern[n] = self.bus.rb(usize::from(self.i) + n)?;
+        }
+        self.pattern_loaded = true;
+        self.audio.set_pattern(&self.audio_pattern, self.pitch);
+        Ok(())
+    }
+    /// PITCH: set the audio pattern playback rate to `vx`
+    pub fn exec_pitch(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
+        if let AddressingMode::Vx(x) = addressing_mode {
+            self.pitch = self.v[x];
+            self.pattern_loaded = true;
+            self.audio.set_pattern(&self.audio_pattern, self.pitch);
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
 
     /// RND: Set `vx` to a random number masked with NN
-    pub fn exec_rnd(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_rnd(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         if let AddressingMode::VxImediate(x, nn) = addressing_mode {
             self.v[x] = rand::random::<u8>() & nn;
+            Ok(())
         } else {
-            unreachable!()
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
 
     // SKNP: Skip next instruction if key with the value of Vx is not pressed.
-    pub fn exec_sknp(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_sknp(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         if let AddressingMode::Vx(x) = addressing_mode {
-            if !((self.keypad & 1 << u16::from(self.v[x])) == 1 << u16::from(self.v[x])) {
-                self.pc += 2;
+            if !self.keypad.is_pressed(self.v[x]) {
+                self.skip_next()?;
             }
+            Ok(())
         } else {
-            unreachable!()
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
         }
     }
 
     // SKP Skip next instruction if key with the value of Vx is pressed.
-    pub fn exec_skp(&mut self, addressing_mode: AddressingMode) {
+    pub fn exec_skp(&mut self, addressing_mode: AddressingMode) -> Result<(), CpuError> {
         if let AddressingMode::Vx(x) = addressing_mode {
-            if (self.keypad & 1 << u16::from(self.v[x])) == 1 << u16::from(self.v[x]) {
-                self.pc += 2;
+            if self.keypad.is_pressed(self.v[x]) {
+                self.skip_next()?;
             }
-        } else {unreachable!()}
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode(addressing_mode))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_is_the_inverse_of_decode() {
+        for word in 0..=0xFFFF {
+            if let Ok(instruction) = Instruction::try_from(word) {
+                assert_eq!(u16::try_from(instruction), Ok(word), "{}", instruction);
+            }
+        }
+
+        let long_load = Instruction::decode(LONG_LOAD, 0xBEEF).unwrap();
+        assert_eq!(long_load.to_bytes(), Ok(vec![0xF0, 0x00, 0xBE, 0xEF]));
+    }
+
+    #[test]
+    fn encode_rejects_illegal_instructions() {
+        let encode = |opcode, mode| Instruction(opcode, mode).encode();
+
+        assert_eq!(
+            encode(Opcode::CLS, AddressingMode::Vx(0)),
+            Err(CpuError::InvalidAddressingMode(AddressingMode::Vx(0)))
+        );
+        assert_eq!(
+            encode(Opcode::LD, AddressingMode::VxVy(0, 0x10)),
+            Err(CpuError::InvalidRegister(0x10))
+        );
+        assert_eq!(
+            encode(Opcode::JP, AddressingMode::Addr(0x1000)),
+            Err(CpuError::AddressOutOfRange(0x1000))
+        );
+        // doesn't fit in a nibble
+        assert!(encode(Opcode::DRW, AddressingMode::VxVyImediate(0, 1, 16)).is_err());
+        // would encode CLS
+        assert!(encode(Opcode::SYS, AddressingMode::Addr(0x0E0)).is_err());
     }
 }
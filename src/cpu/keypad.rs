@@ -0,0 +1,50 @@
+/// The 16 keys (0x0..=0xF) hexadecimal keypad
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Keypad {
+    // one bit per key currently held down
+    pub(crate) pressed: u16,
+    // keys released since the last time they were taken, used by LD Vx, K
+    pub(crate) released: u16,
+}
+
+impl Keypad {
+    pub fn press(&mut self, key: u8) {
+        self.pressed |= 1 << (key & 0xF);
+    }
+
+    pub fn release(&mut self, key: u8) {
+        let mask = 1 << (key & 0xF);
+
+        if self.pressed & mask != 0 {
+            self.pressed &= !mask;
+            self.released |= mask;
+        }
+    }
+
+    // only the low nibble selects a key
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.pressed & 1 << (key & 0xF) != 0
+    }
+
+    // bitmask of the keys held down
+    pub fn state(&self) -> u16 {
+        self.pressed
+    }
+
+    // forget every key released so far
+    pub(crate) fn clear_released(&mut self) {
+        self.released = 0;
+    }
+
+    // take the lowest key that was pressed and then released
+    pub(crate) fn take_released(&mut self) -> Option<u8> {
+        if self.released == 0 {
+            return None;
+        }
+
+        let key = self.released.trailing_zeros() as u8;
+        self.released = 0;
+
+        Some(key)
+    }
+}
@@ -1,20 +1,58 @@
-use std::cell::RefCell;
+use std::convert::TryFrom;
 
+use crate::audio::{AudioSink, NullSink};
+use crate::bus::Bus;
 use crate::mmu::MMU;
+use crate::trace::{TraceRecord, Tracer};
 
-#[allow(dead_code)]
+mod error;
 mod instruction;
+mod keypad;
+mod quirks;
+mod state;
+#[cfg(test)]
+mod tests;
+mod timing;
+
+pub use error::CpuError;
+pub use instruction::{AddressingMode, Instruction, Opcode, LONG_LOAD};
+pub use keypad::Keypad;
+pub use quirks::Quirks;
+pub use timing::{VIP_CYCLES_PER_FRAME, VIP_INTERPRETER_CYCLES};
+
+// pitch for a 4000Hz audio pattern playback rate
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// Host callback invoked with the target address of a `SYS` instruction
+pub type SysHandler<B = MMU> = Box<dyn FnMut(&mut CPU<B>, u16)>;
+
+/// What the cpu should do when it executes a `SYS addr` (0nnn) instruction.
+///
+/// On the original hardware `SYS` jumped into a native machine code routine,
+/// which an interpreter can't run, so the host decides what happens.
+#[derive(Default)]
+pub enum SysPolicy<B: Bus = MMU> {
+    /// Treat `SYS` as a no-op (what most modern interpreters do)
+    #[default]
+    Ignore,
+    /// Hand the target address to a host callback
+    Trap(SysHandler<B>),
+    /// Refuse to run the instruction
+    Error,
+}
 
-use instruction::{Instruction, Opcode};
-
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Status {
     Running,
     Halt,
     WaitingKeypress(usize),
+    // a sprite was drawn and the display wait quirk is on
+    WaitingVblank,
+    // an instruction failed, holding where it was fetched from and its raw opcode
+    Faulted { pc: u16, opcode: u16 },
 }
 
-pub struct CPU {
+pub struct CPU<B: Bus = MMU> {
     // general purpose registers (usually called Vx)
     pub v: [u8; 16],
 
@@ -31,20 +69,53 @@ pub struct CPU {
 
     pub stack: [u16; 16],
 
+    // SUPER-CHIP RPL user flags, kept by the HP-48 across programs (16 on XO-CHIP)
+    pub rpl: [u8; 16],
+
+    // XO-CHIP 1-bit audio pattern and its playback rate
+    pub audio_pattern: [u8; 16],
+    pub pitch: u8,
+    // set once the program loads a pattern or a pitch, played instead of the tone
+    pub pattern_loaded: bool,
+
     // Memory Bus
-    pub bus: RefCell<MMU>,
-    pub keypad: u16,
+    pub bus: B,
+    pub keypad: Keypad,
 
     // cpu status
     pub status: Status,
+
+    // platform behaviour differences
+    pub quirks: Quirks,
+
+    // what to do when a SYS instruction is executed
+    sys_policy: SysPolicy<B>,
+
+    // where the buzzer is played
+    audio: Box<dyn AudioSink>,
+
+    // instructions run so far
+    cycles: u64,
+    // gets a record of every instruction before it runs, when set
+    tracer: Option<Box<dyn Tracer>>,
 }
 
 impl CPU {
-    // create a new cpu
-    pub fn new(bus: MMU) -> Self {
+    // create a new cpu starting at the load address of the mmu
+    pub fn new(bus: MMU, quirks: Quirks) -> Self {
         // rom should already be locked
         assert!(bus.locked_rom());
 
+        let load_address = bus.load_address() as u16;
+        let mut cpu = Self::from_bus(bus, quirks);
+        cpu.pc = load_address;
+        cpu
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    /// Create a cpu running against any [`Bus`], starting at 0x200
+    pub fn from_bus(bus: B, quirks: Quirks) -> Self {
         Self {
             v: [0; 16],
 
@@ -58,10 +129,25 @@ impl CPU {
 
             stack: [0; 16],
 
-            bus: RefCell::new(bus),
-            keypad: 0,
+            rpl: [0; 16],
+
+            audio_pattern: [0; 16],
+            pitch: DEFAULT_PITCH,
+            pattern_loaded: false,
+
+            bus,
+            keypad: Keypad::default(),
 
             status: Status::Running,
+
+            quirks,
+
+            sys_policy: SysPolicy::default(),
+
+            audio: Box::new(NullSink),
+
+            cycles: 0,
+            tracer: None,
         }
     }
 
@@ -69,45 +155,153 @@ impl CPU {
         self.status
     }
 
+    // signal the vertical blank interrupt, called once every frame (60Hz)
+    pub fn vblank(&mut self) {
+        if self.status == Status::WaitingVblank {
+            self.status = Status::Running;
+        }
+    }
+
+    pub fn set_sys_policy(&mut self, policy: SysPolicy<B>) {
+        self.sys_policy = policy;
+    }
+
+    pub fn set_audio_sink(&mut self, mut audio: Box<dyn AudioSink>) {
+        if self.sound > 0 {
+            audio.set_beeping(true);
+        }
+        self.audio = audio;
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) {
+        self.tracer = tracer;
+    }
+
+    // number of instructions run so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // decrement the delay and sound timers, called once every frame (60Hz)
+    pub fn tick_timers(&mut self) {
+        self.audio.tick();
+
+        self.delay = self.delay.saturating_sub(1);
+        self.set_sound(self.sound.saturating_sub(1));
+    }
+
+    // update the sound timer, turning the buzzer on or off on its edges
+    fn set_sound(&mut self, sound: u8) {
+        if (self.sound == 0) != (sound == 0) {
+            self.audio.set_beeping(sound > 0);
+        }
+        self.sound = sound;
+    }
+
     // perform a cpu cycle
-    pub fn cycle(&mut self) {
-        if self.status == Status::Running {
-            let instruction = self.fetch();
-            self.execute(instruction);
+    pub fn cycle(&mut self) -> Result<(), CpuError> {
+        // LD Vx, K resumes once a key is pressed and released, as on the VIP
+        if let Status::WaitingKeypress(x) = self.status {
+            if let Some(key) = self.keypad.take_released() {
+                self.v[x] = key;
+                self.status = Status::Running;
+            }
+            return Ok(());
+        }
+
+        if self.status != Status::Running {
+            return Ok(());
+        }
+
+        let pc = self.pc;
+        let result = self.fetch().and_then(|instruction| {
+            if self.tracer.is_some() {
+                let record = TraceRecord::new(self, self.cycles, pc, instruction.to_string());
+                if let Some(tracer) = self.tracer.as_mut() {
+                    tracer.trace(&record);
+                }
+            }
+            self.cycles += 1;
+
+            self.execute(instruction)
+        });
+
+        if result.is_err() {
+            // record the faulting instruction, if it could be read at all
+            let opcode = self.bus.fetch(usize::from(pc)).unwrap_or(0);
+            self.status = Status::Faulted { pc, opcode };
         }
+
+        result
     }
 
     // fetch and decode an opcode, returning the respective instruction
-    pub fn fetch(&mut self) -> Instruction {
-        let opcode = self.bus.borrow_mut().rw(self.pc as usize);
-        // println!("{:04X}", opcode);
-        let instruction: Instruction = opcode.into();
+    pub fn fetch(&mut self) -> Result<Instruction, CpuError> {
+        let instruction = self.peek(self.pc)?;
+
+        // the address space wraps around, like the 16 bits pc
+        self.pc = self.pc.wrapping_add(instruction.size());
+
+        Ok(instruction)
+    }
 
-        self.pc += 2;
+    // decode the instruction at `addr` without executing it
+    pub fn peek(&self, addr: u16) -> Result<Instruction, CpuError> {
+        let bus = &self.bus;
+        let addr = usize::from(addr);
 
-        instruction
+        let opcode = bus.fetch(addr)?;
+        if opcode == LONG_LOAD {
+            Instruction::decode(opcode, bus.fetch(addr + 2)?)
+        } else {
+            Instruction::try_from(opcode)
+        }
+    }
+
+    // skip the next instruction, which may be 4 bytes long on XO-CHIP
+    fn skip_next(&mut self) -> Result<(), CpuError> {
+        let opcode = self.bus.fetch(usize::from(self.pc))?;
+        self.pc = self
+            .pc
+            .wrapping_add(if opcode == LONG_LOAD { 4 } else { 2 });
+
+        Ok(())
     }
 
     // push a word into stack
-    fn push_stack(&mut self, word: u16) {
-        self.stack[usize::from(self.sp)] = word;
+    fn push_stack(&mut self, word: u16) -> Result<(), CpuError> {
+        let sp = usize::from(self.sp);
+        if sp >= self.stack.len() {
+            return Err(CpuError::StackOverflow);
+        }
+
+        self.stack[sp] = word;
         self.sp += 1;
+
+        Ok(())
     }
     // pop a word from stack
-    fn pop_stack(&mut self) -> u16 {
+    fn pop_stack(&mut self) -> Result<u16, CpuError> {
+        if self.sp == 0 {
+            return Err(CpuError::StackUnderflow);
+        }
+
         self.sp -= 1;
         let sp = usize::from(self.sp);
 
         let word = self.stack[sp];
         self.stack[sp] = 0;
 
-        word
+        Ok(word)
     }
 
     // execute a given instruction
-    pub fn execute(&mut self, instruction: Instruction) {
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), CpuError> {
         let Instruction(opcode, addressing_mode) = instruction;
-        // println!("Running {:06x?}", instruction);
+
+        if let Some(x) = addressing_mode.registers().find(|&x| x > 0xF) {
+            return Err(CpuError::InvalidRegister(x));
+        }
 
         match opcode {
             Opcode::CLS => self.exec_cls(),
@@ -119,6 +313,7 @@ impl CPU {
 
             Opcode::ADD => self.exec_add(addressing_mode),
             Opcode::SUB => self.exec_sub(addressing_mode),
+            Opcode::SUBN => self.exec_subn(addressing_mode),
             Opcode::OR => self.exec_or(addressing_mode),
             Opcode::AND => self.exec_and(addressing_mode),
             Opcode::XOR => self.exec_xor(addressing_mode),
@@ -136,7 +331,18 @@ impl CPU {
             Opcode::SKNP => self.exec_sknp(addressing_mode),
             Opcode::SKP => self.exec_skp(addressing_mode),
 
-            _ => panic!("Instruction not implemented: {:x?}", instruction),
+            Opcode::SYS => self.exec_sys(addressing_mode),
+
+            Opcode::SCD => self.exec_scd(addressing_mode),
+            Opcode::SCR => self.exec_scr(),
+            Opcode::SCL => self.exec_scl(),
+            Opcode::EXIT => self.exec_exit(),
+            Opcode::LOW => self.exec_low(),
+            Opcode::HIGH => self.exec_high(),
+
+            Opcode::PLANE => self.exec_plane(addressing_mode),
+            Opcode::AUDIO => self.exec_audio(),
+            Opcode::PITCH => self.exec_pitch(addressing_mode),
         }
     }
 }
@@ -0,0 +1,88 @@
+/// Behaviour differences between the CHIP-8 implementations ROMs were written for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    /// SHR/SHL (8xy6/8xyE) shift `vy` into `vx` instead of shifting `vx` in place
+    pub shift_uses_vy: bool,
+    /// LD [I], Vx and LD Vx, [I] (Fx55/Fx65) leave I past the last register accessed
+    pub load_store_increments_i: bool,
+    /// With `load_store_increments_i`, I is only moved by `x`, ending on the last register
+    /// accessed instead of past it
+    pub increment_i_by_x: bool,
+    /// JP V0, addr (Bnnn) jumps to `nnn + vx`, `x` being the highest nibble of `nnn`
+    pub jump_with_vx: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    /// OR/AND/XOR (8xy1/8xy2/8xy3) reset VF to zero
+    pub vf_reset: bool,
+    /// DRW waits for the vertical blank interrupt, drawing at most one sprite per frame
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub const fn cosmac_vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            increment_i_by_x: false,
+            jump_with_vx: false,
+            clip_sprites: true,
+            vf_reset: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48, for the HP-48 calculators
+    pub const fn chip48() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_increments_i: true,
+            increment_i_by_x: true,
+            jump_with_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub const fn superchip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            increment_i_by_x: false,
+            jump_with_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo
+    pub const fn xo_chip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            increment_i_by_x: false,
+            jump_with_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+}
+
+/// None of the quirks, the behaviour of this interpreter before the profiles existed
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            increment_i_by_x: false,
+            jump_with_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use super::{Keypad, Status, CPU};
+use crate::mmu::MMU;
+use crate::state::{Chunks, Put, StateError, Writer};
+
+impl CPU {
+    /// Snapshot the whole machine state (registers, timers, stack, keypad and memory)
+    pub fn save_state(&self) -> Vec<u8> {
+        self.write_state(Writer::new())
+    }
+
+    // a save state with the memory left uncompressed, so the same bytes stay at the
+    // same offsets from one snapshot to the next
+    pub(crate) fn snapshot(&self) -> Vec<u8> {
+        self.write_state(Writer::uncompressed())
+    }
+
+    fn write_state(&self, mut writer: Writer) -> Vec<u8> {
+        let mut cpu = Vec::new();
+
+        cpu.extend_from_slice(&self.v);
+        cpu.put_u16(self.pc);
+        cpu.put_u16(self.sp);
+        cpu.put_u16(self.i);
+        cpu.put_u8(self.delay);
+        cpu.put_u8(self.sound);
+        for &word in self.stack.iter() {
+            cpu.put_u16(word);
+        }
+
+        match self.status {
+            Status::Running => cpu.put_u8(0),
+            Status::Halt => cpu.put_u8(1),
+            Status::WaitingKeypress(x) => {
+                cpu.put_u8(2);
+                cpu.put_u8(x as u8);
+            }
+            Status::WaitingVblank => cpu.put_u8(3),
+            Status::Faulted { pc, opcode } => {
+                cpu.put_u8(4);
+                cpu.put_u16(pc);
+                cpu.put_u16(opcode);
+            }
+        }
+
+        cpu.put_u16(self.keypad.pressed);
+        cpu.put_u16(self.keypad.released);
+        cpu.extend_from_slice(&self.rpl);
+        cpu.extend_from_slice(&self.audio_pattern);
+        cpu.put_u8(self.pitch);
+        cpu.put_u8(u8::from(self.pattern_loaded));
+
+        writer.chunk(b"CPU ", &cpu);
+        self.bus.save_state(&mut writer);
+
+        writer.finish()
+    }
+
+    /// Restore a state made by [`CPU::save_state`].
+    ///
+    /// Nothing is changed unless the whole state is valid. The quirks,
+    /// SYS policy and audio sink are configuration and are kept as they are.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let chunks = Chunks::parse(state)?;
+        let mut cpu = chunks.get(b"CPU ")?;
+
+        let mut v = [0; 16];
+        v.copy_from_slice(cpu.bytes(16)?);
+        let pc = cpu.u16()?;
+        let sp = cpu.u16()?;
+        let i = cpu.u16()?;
+        let delay = cpu.u8()?;
+        let sound = cpu.u8()?;
+
+        let mut stack = [0; 16];
+        for word in stack.iter_mut() {
+            *word = cpu.u16()?;
+        }
+        if usize::from(sp) > stack.len() {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+
+        let status = match cpu.u8()? {
+            0 => Status::Running,
+            1 => Status::Halt,
+            2 => match cpu.u8()? {
+                x if x <= 0xF => Status::WaitingKeypress(usize::from(x)),
+                _ => return Err(StateError::Invalid("status")),
+            },
+            3 => Status::WaitingVblank,
+            4 => Status::Faulted {
+                pc: cpu.u16()?,
+                opcode: cpu.u16()?,
+            },
+            _ => return Err(StateError::Invalid("status")),
+        };
+
+        let keypad = Keypad {
+            pressed: cpu.u16()?,
+            released: cpu.u16()?,
+        };
+
+        let mut rpl = [0; 16];
+        rpl.copy_from_slice(cpu.bytes(16)?);
+        let mut audio_pattern = [0; 16];
+        audio_pattern.copy_from_slice(cpu.bytes(16)?);
+        let pitch = cpu.u8()?;
+        let pattern_loaded = cpu.u8()? != 0;
+
+        let mut mmu = MMU::load_state(&chunks)?;
+        // the protection is a setting of the emulator rather than machine state
+        mmu.set_protection(self.bus.protection());
+
+        self.v = v;
+        self.pc = pc;
+        self.sp = sp;
+        self.i = i;
+        self.delay = delay;
+        self.set_sound(sound);
+        self.stack = stack;
+        self.status = status;
+        self.keypad = keypad;
+        self.rpl = rpl;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.pattern_loaded = pattern_loaded;
+        self.bus = mmu;
+
+        if self.pattern_loaded {
+            self.audio.set_pattern(&self.audio_pattern, self.pitch);
+        } else {
+            self.audio.clear_pattern();
+        }
+
+        Ok(())
+    }
+}
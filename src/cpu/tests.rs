@@ -0,0 +1,1116 @@
+//! Table-driven tests of every executor: each case sets up a cpu, runs a single
+//! instruction and checks the registers, memory and screen it ends with. Registers
+//! that aren't expected to change must keep the value they were set up with.
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+use super::*;
+use crate::bus::Bus;
+use crate::mmu::{MemoryError, Protection, Region, BIG_FONT_OFFSET};
+
+// the cpu a case starts from
+struct Setup {
+    quirks: Quirks,
+    xo_chip: bool,
+    protection: Protection,
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    stack: Vec<u16>,
+    delay: u8,
+    sound: u8,
+    rpl: [u8; 16],
+    memory: Vec<(usize, Vec<u8>)>,
+    pixels: Vec<(usize, usize)>,
+    keys: Vec<u8>,
+}
+
+fn cpu() -> Setup {
+    Setup {
+        quirks: Quirks::cosmac_vip(),
+        xo_chip: false,
+        protection: Protection::default(),
+        v: [0; 16],
+        i: 0,
+        pc: 0x200,
+        stack: Vec::new(),
+        delay: 0,
+        sound: 0,
+        rpl: [0; 16],
+        memory: Vec::new(),
+        pixels: Vec::new(),
+        keys: Vec::new(),
+    }
+}
+
+impl Setup {
+    fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+    // the 64KiB address space
+    fn xo_chip(mut self) -> Self {
+        self.xo_chip = true;
+        self
+    }
+    fn protection(mut self, protection: Protection) -> Self {
+        self.protection = protection;
+        self
+    }
+    fn v(mut self, x: usize, value: u8) -> Self {
+        self.v[x] = value;
+        self
+    }
+    fn i(mut self, i: u16) -> Self {
+        self.i = i;
+        self
+    }
+    fn stack(mut self, stack: &[u16]) -> Self {
+        self.stack = stack.to_vec();
+        self
+    }
+    fn delay(mut self, delay: u8) -> Self {
+        self.delay = delay;
+        self
+    }
+    fn rpl(mut self, x: usize, value: u8) -> Self {
+        self.rpl[x] = value;
+        self
+    }
+    fn memory(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.memory.push((offset, bytes.to_vec()));
+        self
+    }
+    fn pixel(mut self, x: usize, y: usize) -> Self {
+        self.pixels.push((x, y));
+        self
+    }
+    fn key(mut self, key: u8) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    fn build(&self) -> CPU {
+        let mut mmu = if self.xo_chip {
+            MMU::xo_chip()
+        } else {
+            MMU::default()
+        };
+        for (offset, bytes) in &self.memory {
+            for (n, &byte) in bytes.iter().enumerate() {
+                mmu.wb(offset + n, byte).unwrap();
+            }
+        }
+        for &(x, y) in &self.pixels {
+            mmu.wb_vram(x, y, 1);
+        }
+        mmu.lock_rom();
+        mmu.set_protection(self.protection);
+
+        let mut cpu = CPU::new(mmu, self.quirks);
+        cpu.v = self.v;
+        cpu.i = self.i;
+        cpu.pc = self.pc;
+        cpu.stack[..self.stack.len()].copy_from_slice(&self.stack);
+        cpu.sp = self.stack.len() as u16;
+        cpu.delay = self.delay;
+        cpu.sound = self.sound;
+        cpu.rpl = self.rpl;
+        for &key in &self.keys {
+            cpu.keypad.press(key);
+        }
+
+        cpu
+    }
+}
+
+// what a case should end with, anything left out must be unchanged
+#[derive(Default)]
+struct Expected {
+    v: Vec<(usize, u8)>,
+    i: Option<u16>,
+    pc: Option<u16>,
+    stack: Option<Vec<u16>>,
+    delay: Option<u8>,
+    sound: Option<u8>,
+    rpl: Vec<(usize, u8)>,
+    memory: Vec<(usize, Vec<u8>)>,
+    lit: Option<Vec<(usize, usize)>>,
+    hires: Option<bool>,
+    planes: Option<u8>,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: Option<u8>,
+    status: Option<Status>,
+    error: Option<CpuError>,
+}
+
+fn expect() -> Expected {
+    Expected::default()
+}
+
+impl Expected {
+    fn v(mut self, x: usize, value: u8) -> Self {
+        self.v.push((x, value));
+        self
+    }
+    fn vf(self, value: u8) -> Self {
+        self.v(0xF, value)
+    }
+    fn i(mut self, i: u16) -> Self {
+        self.i = Some(i);
+        self
+    }
+    fn pc(mut self, pc: u16) -> Self {
+        self.pc = Some(pc);
+        self
+    }
+    // pc moved past the next instruction
+    fn skipped(self) -> Self {
+        self.pc(0x202)
+    }
+    fn stack(mut self, stack: &[u16]) -> Self {
+        self.stack = Some(stack.to_vec());
+        self
+    }
+    fn delay(mut self, delay: u8) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+    fn sound(mut self, sound: u8) -> Self {
+        self.sound = Some(sound);
+        self
+    }
+    fn rpl(mut self, x: usize, value: u8) -> Self {
+        self.rpl.push((x, value));
+        self
+    }
+    fn memory(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.memory.push((offset, bytes.to_vec()));
+        self
+    }
+    // every lit pixel of the screen
+    fn lit(mut self, pixels: &[(usize, usize)]) -> Self {
+        self.lit = Some(pixels.to_vec());
+        self
+    }
+    fn hires(mut self, hires: bool) -> Self {
+        self.hires = Some(hires);
+        self
+    }
+    fn planes(mut self, planes: u8) -> Self {
+        self.planes = Some(planes);
+        self
+    }
+    fn audio_pattern(mut self, pattern: [u8; 16]) -> Self {
+        self.audio_pattern = Some(pattern);
+        self
+    }
+    fn pitch(mut self, pitch: u8) -> Self {
+        self.pitch = Some(pitch);
+        self
+    }
+    fn status(mut self, status: Status) -> Self {
+        self.status = Some(status);
+        self
+    }
+    fn error(mut self, error: CpuError) -> Self {
+        self.error = Some(error);
+        self
+    }
+}
+
+struct Case {
+    name: &'static str,
+    setup: Setup,
+    instruction: Instruction,
+    expected: Expected,
+}
+
+fn case(name: &'static str, setup: Setup, instruction: Instruction, expected: Expected) -> Case {
+    Case {
+        name,
+        setup,
+        instruction,
+        expected,
+    }
+}
+
+fn op(opcode: u16) -> Instruction {
+    Instruction::try_from(opcode).unwrap()
+}
+
+fn lit_pixels(cpu: &CPU) -> Vec<(usize, usize)> {
+    let bus = &cpu.bus;
+    let width = bus.width();
+
+    (0..width * bus.height())
+        .filter(|&n| bus.vram[n] != 0)
+        .map(|n| (n % width, n / width))
+        .collect()
+}
+
+impl Case {
+    fn run(&self) {
+        let name = self.name;
+        let mut cpu = self.setup.build();
+        let result = cpu.execute(self.instruction);
+        assert_eq!(result.err(), self.expected.error, "{}: result", name);
+
+        let expected = &self.expected;
+
+        let mut v = self.setup.v;
+        for &(x, value) in &expected.v {
+            v[x] = value;
+        }
+        for (x, (&actual, &expected)) in cpu.v.iter().zip(&v).enumerate() {
+            assert_eq!(actual, expected, "{}: V{:X}", name, x);
+        }
+
+        assert_eq!(cpu.i, expected.i.unwrap_or(self.setup.i), "{}: I", name);
+        assert_eq!(cpu.pc, expected.pc.unwrap_or(self.setup.pc), "{}: PC", name);
+
+        let stack = expected.stack.as_ref().unwrap_or(&self.setup.stack);
+        assert_eq!(
+            &cpu.stack[..usize::from(cpu.sp)],
+            &stack[..],
+            "{}: stack",
+            name
+        );
+
+        let delay = expected.delay.unwrap_or(self.setup.delay);
+        assert_eq!(cpu.delay, delay, "{}: DT", name);
+        let sound = expected.sound.unwrap_or(self.setup.sound);
+        assert_eq!(cpu.sound, sound, "{}: ST", name);
+
+        let mut rpl = self.setup.rpl;
+        for &(x, value) in &expected.rpl {
+            rpl[x] = value;
+        }
+        assert_eq!(cpu.rpl, rpl, "{}: RPL flags", name);
+
+        for (offset, bytes) in &expected.memory {
+            let memory: Vec<u8> = (0..bytes.len())
+                .map(|n| cpu.bus.peek(offset + n).unwrap())
+                .collect();
+            assert_eq!(&memory, bytes, "{}: memory at {:03X}", name, offset);
+        }
+
+        if let Some(lit) = &expected.lit {
+            assert_eq!(&lit_pixels(&cpu), lit, "{}: screen", name);
+        }
+        if let Some(hires) = expected.hires {
+            assert_eq!(cpu.bus.hires(), hires, "{}: hires", name);
+        }
+        if let Some(planes) = expected.planes {
+            assert_eq!(cpu.bus.planes(), planes, "{}: planes", name);
+        }
+        if let Some(pattern) = expected.audio_pattern {
+            assert_eq!(cpu.audio_pattern, pattern, "{}: audio pattern", name);
+        }
+        if let Some(pitch) = expected.pitch {
+            assert_eq!(cpu.pitch, pitch, "{}: pitch", name);
+        }
+        assert_eq!(
+            cpu.status,
+            expected.status.unwrap_or(Status::Running),
+            "{}: status",
+            name
+        );
+    }
+}
+
+fn check(cases: Vec<Case>) {
+    for case in cases {
+        case.run();
+    }
+}
+
+fn control_flow() -> Vec<Case> {
+    vec![
+        case("JP addr", cpu(), op(0x1345), expect().pc(0x345)),
+        case(
+            "JP V0, addr",
+            cpu().v(0, 0x10),
+            op(0xB345),
+            expect().pc(0x355),
+        ),
+        case(
+            "JP V0, addr jumps with VX",
+            cpu().quirks(Quirks::superchip()).v(0, 0x10).v(3, 0x20),
+            op(0xB345),
+            expect().pc(0x365),
+        ),
+        case(
+            "CALL pushes the return address",
+            cpu().stack(&[0x400]),
+            op(0x2345),
+            expect().pc(0x345).stack(&[0x400, 0x200]),
+        ),
+        case(
+            "CALL with a full stack",
+            cpu().stack(&[0x400; 16]),
+            op(0x2345),
+            expect().error(CpuError::StackOverflow),
+        ),
+        case(
+            "RET pops the return address",
+            cpu().stack(&[0x400, 0x300]),
+            op(0x00EE),
+            expect().pc(0x300).stack(&[0x400]),
+        ),
+        case(
+            "RET with an empty stack",
+            cpu(),
+            op(0x00EE),
+            expect().error(CpuError::StackUnderflow),
+        ),
+        case("SYS is ignored", cpu(), op(0x0345), expect()),
+        case(
+            "EXIT halts",
+            cpu(),
+            op(0x00FD),
+            expect().status(Status::Halt),
+        ),
+        case(
+            "SE Vx, byte skips",
+            cpu().v(1, 0x42),
+            op(0x3142),
+            expect().skipped(),
+        ),
+        case("SE Vx, byte", cpu().v(1, 0x42), op(0x3143), expect()),
+        case(
+            "SNE Vx, byte skips",
+            cpu().v(1, 0x42),
+            op(0x4143),
+            expect().skipped(),
+        ),
+        case("SNE Vx, byte", cpu().v(1, 0x42), op(0x4142), expect()),
+        case(
+            "SE Vx, Vy skips",
+            cpu().v(1, 0x42).v(2, 0x42),
+            op(0x5120),
+            expect().skipped(),
+        ),
+        case("SE Vx, Vy", cpu().v(1, 0x42), op(0x5120), expect()),
+        case(
+            "SNE Vx, Vy skips",
+            cpu().v(1, 0x42),
+            op(0x9120),
+            expect().skipped(),
+        ),
+        case(
+            "SNE Vx, Vy",
+            cpu().v(1, 0x42).v(2, 0x42),
+            op(0x9120),
+            expect(),
+        ),
+        case(
+            "SE skips a long load",
+            cpu().memory(0x200, &[0xF0, 0x00, 0x12, 0x34]),
+            op(0x3100),
+            expect().pc(0x204),
+        ),
+        case(
+            "SKP Vx skips",
+            cpu().v(1, 0xA).key(0xA),
+            op(0xE19E),
+            expect().skipped(),
+        ),
+        case("SKP Vx", cpu().v(1, 0xA).key(0xB), op(0xE19E), expect()),
+        case(
+            "SKNP Vx skips",
+            cpu().v(1, 0xA),
+            op(0xE1A1),
+            expect().skipped(),
+        ),
+        case("SKNP Vx", cpu().v(1, 0xA).key(0xA), op(0xE1A1), expect()),
+    ]
+}
+
+fn loads() -> Vec<Case> {
+    vec![
+        case("LD Vx, byte", cpu(), op(0x6A42), expect().v(0xA, 0x42)),
+        case(
+            "LD Vx, Vy",
+            cpu().v(2, 0x42),
+            op(0x8120),
+            expect().v(1, 0x42),
+        ),
+        case("LD I, addr", cpu(), op(0xA345), expect().i(0x345)),
+        case(
+            "LD I, LONG addr",
+            cpu(),
+            Instruction::decode(LONG_LOAD, 0xABCD).unwrap(),
+            expect().i(0xABCD),
+        ),
+        case(
+            "LD Vx, DT",
+            cpu().delay(0x42),
+            op(0xF107),
+            expect().v(1, 0x42),
+        ),
+        case(
+            "LD DT, Vx",
+            cpu().v(1, 0x42),
+            op(0xF115),
+            expect().delay(0x42),
+        ),
+        case(
+            "LD ST, Vx",
+            cpu().v(1, 0x42),
+            op(0xF118),
+            expect().sound(0x42),
+        ),
+        case(
+            "LD Vx, K waits for a key",
+            cpu(),
+            op(0xF30A),
+            expect().status(Status::WaitingKeypress(3)),
+        ),
+        case(
+            "LD F, Vx uses the low nibble",
+            cpu().v(1, 0x1A),
+            op(0xF129),
+            expect().i(50),
+        ),
+        case(
+            "LD HF, Vx",
+            cpu().v(1, 0x2),
+            op(0xF130),
+            expect().i(BIG_FONT_OFFSET as u16 + 20),
+        ),
+        case(
+            "LD R, Vx",
+            cpu().v(0, 1).v(1, 2).v(2, 3).v(3, 4),
+            op(0xF275),
+            expect().rpl(0, 1).rpl(1, 2).rpl(2, 3),
+        ),
+        case(
+            "LD Vx, R",
+            cpu().rpl(0, 1).rpl(1, 2).rpl(2, 3).rpl(3, 4),
+            op(0xF285),
+            expect().v(0, 1).v(1, 2).v(2, 3),
+        ),
+    ]
+}
+
+fn memory() -> Vec<Case> {
+    vec![
+        case(
+            "LD B, Vx",
+            cpu().v(1, 137).i(0x300),
+            op(0xF133),
+            expect().memory(0x300, &[1, 3, 7]),
+        ),
+        case(
+            "LD B, Vx with 255",
+            cpu().v(1, 255).i(0x300),
+            op(0xF133),
+            expect().memory(0x300, &[2, 5, 5]),
+        ),
+        case(
+            "LD B, Vx with 0",
+            cpu().i(0x300).memory(0x300, &[9, 9, 9]),
+            op(0xF133),
+            expect().memory(0x300, &[0, 0, 0]),
+        ),
+        case(
+            "LD [I], Vx moves I",
+            cpu().v(0, 1).v(1, 2).v(2, 3).v(3, 4).i(0x300),
+            op(0xF255),
+            expect().memory(0x300, &[1, 2, 3, 0]).i(0x303),
+        ),
+        case(
+            "LD [I], Vx keeps I",
+            cpu()
+                .quirks(Quirks::superchip())
+                .v(0, 1)
+                .v(1, 2)
+                .v(2, 3)
+                .i(0x300),
+            op(0xF255),
+            expect().memory(0x300, &[1, 2, 3]),
+        ),
+        case(
+            "LD Vx, [I] moves I",
+            cpu().i(0x300).memory(0x300, &[1, 2, 3, 4]),
+            op(0xF265),
+            expect().v(0, 1).v(1, 2).v(2, 3).i(0x303),
+        ),
+        case(
+            "LD Vx, [I] keeps I",
+            cpu()
+                .quirks(Quirks::superchip())
+                .i(0x300)
+                .memory(0x300, &[1, 2, 3]),
+            op(0xF265),
+            expect().v(0, 1).v(1, 2).v(2, 3),
+        ),
+        case(
+            "LD Vx, [I] moves I by x",
+            cpu()
+                .quirks(Quirks::chip48())
+                .i(0x300)
+                .memory(0x300, &[1, 2, 3]),
+            op(0xF265),
+            expect().v(0, 1).v(1, 2).v(2, 3).i(0x302),
+        ),
+        case(
+            "LD Vx, [I] moving I wraps around",
+            cpu().xo_chip().i(0xFFFE).memory(0xFFFE, &[7, 8]),
+            op(0xF165),
+            expect().v(0, 7).v(1, 8).i(0),
+        ),
+        case(
+            "LD [I], Vx past the end of memory",
+            cpu().v(0, 9).i(0xFFF),
+            op(0xF155),
+            expect()
+                .memory(0xFFF, &[9])
+                .error(CpuError::AddressOutOfRange(0x1000)),
+        ),
+        case(
+            "LD [I], Vx-Vy",
+            cpu().v(1, 1).v(2, 2).v(3, 3).i(0x300),
+            op(0x5132),
+            expect().memory(0x300, &[1, 2, 3]),
+        ),
+        case(
+            "LD [I], Vx-Vy in reverse",
+            cpu().v(1, 1).v(2, 2).v(3, 3).i(0x300),
+            op(0x5312),
+            expect().memory(0x300, &[3, 2, 1]),
+        ),
+        case(
+            "LD Vx-Vy, [I]",
+            cpu().i(0x300).memory(0x300, &[1, 2, 3]),
+            op(0x5133),
+            expect().v(1, 1).v(2, 2).v(3, 3),
+        ),
+        case(
+            "LD Vx-Vy, [I] in reverse",
+            cpu().i(0x300).memory(0x300, &[1, 2, 3]),
+            op(0x5313),
+            expect().v(3, 1).v(2, 2).v(1, 3),
+        ),
+    ]
+}
+
+fn arithmetic() -> Vec<Case> {
+    vec![
+        case(
+            "ADD Vx, byte wraps without touching VF",
+            cpu().v(1, 0xFE).v(0xF, 0x42),
+            op(0x7103),
+            expect().v(1, 0x01),
+        ),
+        case(
+            "ADD Vx, Vy",
+            cpu().v(1, 0x10).v(2, 0x20).v(0xF, 1),
+            op(0x8124),
+            expect().v(1, 0x30).vf(0),
+        ),
+        case(
+            "ADD Vx, Vy carries",
+            cpu().v(1, 0xF0).v(2, 0x20),
+            op(0x8124),
+            expect().v(1, 0x10).vf(1),
+        ),
+        case(
+            "ADD VF, Vy keeps the carry",
+            cpu().v(0xF, 0xF0).v(2, 0x20),
+            op(0x8F24),
+            expect().vf(1),
+        ),
+        case(
+            "ADD Vx, VF uses VF before the carry",
+            cpu().v(1, 0x10).v(0xF, 0x20),
+            op(0x81F4),
+            expect().v(1, 0x30).vf(0),
+        ),
+        case(
+            "ADD I, Vx",
+            cpu().i(0x300).v(1, 0x42),
+            op(0xF11E),
+            expect().i(0x342),
+        ),
+        case(
+            "ADD I, Vx wraps around",
+            cpu().i(0xFFFF).v(1, 2),
+            op(0xF11E),
+            expect().i(0x0001),
+        ),
+        case(
+            "SUB Vx, Vy",
+            cpu().v(1, 0x30).v(2, 0x10),
+            op(0x8125),
+            expect().v(1, 0x20).vf(1),
+        ),
+        case(
+            "SUB Vx, Vy borrows",
+            cpu().v(1, 0x10).v(2, 0x30).v(0xF, 1),
+            op(0x8125),
+            expect().v(1, 0xE0).vf(0),
+        ),
+        case(
+            "SUB Vx, Vy of equal values",
+            cpu().v(1, 0x10).v(2, 0x10),
+            op(0x8125),
+            expect().v(1, 0).vf(1),
+        ),
+        case(
+            "SUB VF, Vy keeps the borrow",
+            cpu().v(0xF, 0x10).v(2, 0x30),
+            op(0x8F25),
+            expect().vf(0),
+        ),
+        case(
+            "SUBN Vx, Vy",
+            cpu().v(1, 0x10).v(2, 0x30),
+            op(0x8127),
+            expect().v(1, 0x20).vf(1),
+        ),
+        case(
+            "SUBN Vx, Vy borrows",
+            cpu().v(1, 0x30).v(2, 0x10).v(0xF, 1),
+            op(0x8127),
+            expect().v(1, 0xE0).vf(0),
+        ),
+        case(
+            "SUBN VF, Vy keeps the flag",
+            cpu().v(0xF, 0x10).v(2, 0x30),
+            op(0x8F27),
+            expect().vf(1),
+        ),
+        case(
+            "RND Vx, 0 is always 0",
+            cpu().v(1, 0x42),
+            op(0xC100),
+            expect().v(1, 0),
+        ),
+    ]
+}
+
+fn logic() -> Vec<Case> {
+    vec![
+        case(
+            "OR Vx, Vy resets VF",
+            cpu().v(1, 0xF0).v(2, 0x0F).v(0xF, 1),
+            op(0x8121),
+            expect().v(1, 0xFF).vf(0),
+        ),
+        case(
+            "OR Vx, Vy keeps VF",
+            cpu()
+                .quirks(Quirks::superchip())
+                .v(1, 0xF0)
+                .v(2, 0x0F)
+                .v(0xF, 1),
+            op(0x8121),
+            expect().v(1, 0xFF),
+        ),
+        case(
+            "AND Vx, Vy resets VF",
+            cpu().v(1, 0xF3).v(2, 0x3F).v(0xF, 1),
+            op(0x8122),
+            expect().v(1, 0x33).vf(0),
+        ),
+        case(
+            "AND Vx, Vy keeps VF",
+            cpu()
+                .quirks(Quirks::superchip())
+                .v(1, 0xF3)
+                .v(2, 0x3F)
+                .v(0xF, 1),
+            op(0x8122),
+            expect().v(1, 0x33),
+        ),
+        case(
+            "XOR Vx, Vy resets VF",
+            cpu().v(1, 0xF3).v(2, 0x3F).v(0xF, 1),
+            op(0x8123),
+            expect().v(1, 0xCC).vf(0),
+        ),
+        case(
+            "XOR Vx, Vy keeps VF",
+            cpu()
+                .quirks(Quirks::superchip())
+                .v(1, 0xF3)
+                .v(2, 0x3F)
+                .v(0xF, 1),
+            op(0x8123),
+            expect().v(1, 0xCC),
+        ),
+        case(
+            "SHR Vx, Vy shifts Vy",
+            cpu().v(1, 0x80).v(2, 0x05),
+            op(0x8126),
+            expect().v(1, 0x02).vf(1),
+        ),
+        case(
+            "SHR Vx, Vy shifts Vx",
+            cpu().quirks(Quirks::superchip()).v(1, 0x04).v(2, 0x05),
+            op(0x8126),
+            expect().v(1, 0x02).vf(0),
+        ),
+        case(
+            "SHR VF, Vy keeps the flag",
+            cpu().v(2, 0x05),
+            op(0x8F26),
+            expect().vf(1),
+        ),
+        case(
+            "SHL Vx, Vy shifts Vy",
+            cpu().v(1, 0x01).v(2, 0x81),
+            op(0x812E),
+            expect().v(1, 0x02).vf(1),
+        ),
+        case(
+            "SHL Vx, Vy shifts Vx",
+            cpu().quirks(Quirks::superchip()).v(1, 0x41).v(2, 0x81),
+            op(0x812E),
+            expect().v(1, 0x82).vf(0),
+        ),
+        case(
+            "SHL VF, Vy keeps the flag",
+            cpu().v(2, 0x41),
+            op(0x8F2E),
+            expect().vf(0),
+        ),
+    ]
+}
+
+fn display() -> Vec<Case> {
+    vec![
+        case(
+            "CLS",
+            cpu().pixel(1, 2).pixel(3, 4),
+            op(0x00E0),
+            expect().lit(&[]),
+        ),
+        case(
+            "DRW Vx, Vy, n",
+            cpu().v(1, 2).v(2, 3).i(0x300).memory(0x300, &[0xC0, 0x80]),
+            op(0xD122),
+            expect()
+                .lit(&[(2, 3), (3, 3), (2, 4)])
+                .vf(0)
+                .status(Status::WaitingVblank),
+        ),
+        case(
+            "DRW Vx, Vy, n collides",
+            cpu()
+                .quirks(Quirks::superchip())
+                .v(1, 2)
+                .pixel(2, 0)
+                .i(0x300)
+                .memory(0x300, &[0xC0]),
+            op(0xD121),
+            expect().lit(&[(3, 0)]).vf(1),
+        ),
+        case(
+            "DRW Vx, Vy, n wraps the position",
+            cpu()
+                .quirks(Quirks::superchip())
+                .v(1, 66)
+                .v(2, 33)
+                .i(0x300)
+                .memory(0x300, &[0x80]),
+            op(0xD121),
+            expect().lit(&[(2, 1)]).vf(0),
+        ),
+        case(
+            "DRW Vx, Vy, n clips",
+            cpu()
+                .quirks(Quirks::superchip())
+                .v(1, 62)
+                .i(0x300)
+                .memory(0x300, &[0xF0]),
+            op(0xD121),
+            expect().lit(&[(62, 0), (63, 0)]).vf(0),
+        ),
+        case(
+            "DRW Vx, Vy, n wraps around",
+            cpu()
+                .quirks(Quirks::xo_chip())
+                .v(1, 62)
+                .i(0x300)
+                .memory(0x300, &[0xF0]),
+            op(0xD121),
+            expect().lit(&[(0, 0), (1, 0), (62, 0), (63, 0)]).vf(0),
+        ),
+        case(
+            "SCD n",
+            cpu().pixel(1, 2),
+            op(0x00C3),
+            expect().lit(&[(1, 5)]),
+        ),
+        case(
+            "SCR",
+            cpu().pixel(1, 2),
+            op(0x00FB),
+            expect().lit(&[(5, 2)]),
+        ),
+        case(
+            "SCL",
+            cpu().pixel(5, 2),
+            op(0x00FC),
+            expect().lit(&[(1, 2)]),
+        ),
+        case("HIGH", cpu(), op(0x00FF), expect().hires(true)),
+        case("LOW", cpu(), op(0x00FE), expect().hires(false)),
+        case("PLANE n", cpu(), op(0xF201), expect().planes(2)),
+    ]
+}
+
+fn audio() -> Vec<Case> {
+    let pattern = [
+        0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+        0xFF,
+    ];
+
+    vec![
+        case(
+            "AUDIO",
+            cpu().i(0x300).memory(0x300, &pattern),
+            op(0xF002),
+            expect().audio_pattern(pattern),
+        ),
+        case(
+            "PITCH Vx",
+            cpu().v(1, 0x42),
+            op(0xF13A),
+            expect().pitch(0x42),
+        ),
+    ]
+}
+
+fn errors() -> Vec<Case> {
+    vec![
+        case(
+            "an addressing mode the opcode doesn't take",
+            cpu(),
+            Instruction(Opcode::SUB, AddressingMode::Implicit),
+            expect().error(CpuError::InvalidAddressingMode(AddressingMode::Implicit)),
+        ),
+        case(
+            "a register past VF",
+            cpu(),
+            Instruction(Opcode::LD, AddressingMode::VxR(16)),
+            expect().error(CpuError::InvalidRegister(16)),
+        ),
+        case(
+            "a store into the font is dropped",
+            cpu().v(0, 0xAA).i(0x000),
+            op(0xF055),
+            expect().i(0x001).memory(0x000, &[0x60]),
+        ),
+        case(
+            "a store into the font faults when protected",
+            cpu().protection(Protection::strict()).v(0, 0xAA).i(0x000),
+            op(0xF055),
+            expect().error(CpuError::ProtectedWrite {
+                offset: 0x000,
+                region: Region::Font,
+            }),
+        ),
+    ]
+}
+
+#[test]
+fn executes_control_flow() {
+    check(control_flow());
+}
+
+#[test]
+fn executes_loads() {
+    check(loads());
+}
+
+#[test]
+fn executes_memory_accesses() {
+    check(memory());
+}
+
+#[test]
+fn executes_arithmetic() {
+    check(arithmetic());
+}
+
+#[test]
+fn executes_logic_and_shifts() {
+    check(logic());
+}
+
+#[test]
+fn executes_display_instructions() {
+    check(display());
+}
+
+#[test]
+fn executes_audio_instructions() {
+    check(audio());
+}
+
+#[test]
+fn reports_errors() {
+    check(errors());
+}
+
+// the index of every addressing mode, which fails to build when one is added
+fn mode_index(mode: AddressingMode) -> usize {
+    use AddressingMode::*;
+
+    match mode {
+        Implicit => 0,
+        Nibble(_) => 1,
+        Addr(_) => 2,
+        Vx(_) => 3,
+        VxImediate(..) => 4,
+        VxDT(_) => 5,
+        VxKey(_) => 6,
+        VxMem(_) => 7,
+        VxVy(..) => 8,
+        VxVyImediate(..) => 9,
+        V0Addr(_) => 10,
+        IAddr(_) => 11,
+        ILongAddr(_) => 12,
+        IVx(_) => 13,
+        FVx(_) => 14,
+        BVx(_) => 15,
+        MemVx(_) => 16,
+        MemVxVy(..) => 17,
+        VxVyMem(..) => 18,
+        DTVx(_) => 19,
+        STVx(_) => 20,
+        HFVx(_) => 21,
+        RVx(_) => 22,
+        VxR(_) => 23,
+    }
+}
+
+#[test]
+fn covers_every_addressing_mode() {
+    let tables = [
+        control_flow(),
+        loads(),
+        memory(),
+        arithmetic(),
+        logic(),
+        display(),
+        audio(),
+    ];
+
+    let covered: BTreeSet<usize> = tables
+        .iter()
+        .flatten()
+        .map(|case| mode_index(case.instruction.1))
+        .collect();
+    assert_eq!(covered, (0..24).collect());
+}
+
+// mirrors the 4KiB of a mmu across the whole address space, keeping the writes
+struct MirroredBus {
+    mmu: MMU,
+    writes: Vec<(usize, u8)>,
+}
+
+impl Bus for MirroredBus {
+    fn rb(&mut self, offset: usize) -> Result<u8, MemoryError> {
+        self.mmu.rb(offset % 0x1000)
+    }
+    fn wb(&mut self, offset: usize, byte: u8) -> Result<(), MemoryError> {
+        self.writes.push((offset, byte));
+        self.mmu.wb(offset % 0x1000, byte)
+    }
+    fn peek(&self, offset: usize) -> Result<u8, MemoryError> {
+        self.mmu.peek(offset % 0x1000)
+    }
+
+    fn width(&self) -> usize {
+        self.mmu.width()
+    }
+    fn height(&self) -> usize {
+        self.mmu.height()
+    }
+    fn hires(&self) -> bool {
+        self.mmu.hires()
+    }
+    fn set_hires(&mut self, hires: bool) {
+        self.mmu.set_hires(hires)
+    }
+    fn planes(&self) -> u8 {
+        self.mmu.planes()
+    }
+    fn select_planes(&mut self, planes: u8) {
+        self.mmu.select_planes(planes)
+    }
+    fn rb_vram(&self, x: usize, y: usize) -> u8 {
+        self.mmu.rb_vram(x, y)
+    }
+    fn clear_vram(&mut self) {
+        self.mmu.clear_vram()
+    }
+    fn scroll_down(&mut self, n: usize) {
+        self.mmu.scroll_down(n)
+    }
+    fn scroll_right(&mut self, n: usize) {
+        self.mmu.scroll_right(n)
+    }
+    fn scroll_left(&mut self, n: usize) {
+        self.mmu.scroll_left(n)
+    }
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], width: usize, clip: bool) -> bool {
+        self.mmu.draw_sprite(x, y, sprite, width, clip)
+    }
+}
+
+#[test]
+fn runs_on_any_bus() {
+    let mut mmu = MMU::default();
+    // LD B, V1; JP 200
+    for (n, &byte) in [0xF1, 0x33, 0x12, 0x00].iter().enumerate() {
+        mmu.wb(0x200 + n, byte).unwrap();
+    }
+    let bus = MirroredBus {
+        mmu,
+        writes: Vec::new(),
+    };
+
+    let mut cpu = CPU::from_bus(bus, Quirks::default());
+    // running from a mirror of the program
+    cpu.pc = 0x1200;
+    cpu.v[1] = 137;
+    cpu.i = 0x2300;
+    cpu.cycle().unwrap();
+    cpu.cycle().unwrap();
+
+    assert_eq!(cpu.bus.writes, vec![(0x2300, 1), (0x2301, 3), (0x2302, 7)]);
+    assert_eq!(cpu.bus.mmu.peek(0x300), Ok(1));
+    assert_eq!(cpu.pc, 0x200);
+}
+
+#[test]
+fn pc_wraps_around_the_end_of_memory() {
+    let mut mmu = MMU::xo_chip();
+    // SE V0, 0 skipping the long I load, whose address reads as CLS
+    for (n, &byte) in [0x30, 0x00, 0xF0, 0x00, 0x00, 0xE0].iter().enumerate() {
+        mmu.wb(0xFFFA + n, byte).unwrap();
+    }
+    mmu.lock_rom();
+
+    let mut cpu = CPU::new(mmu, Quirks::xo_chip());
+    cpu.pc = 0xFFFA;
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc, 0x0000);
+
+    cpu.pc = 0xFFFC;
+    cpu.cycle().unwrap();
+    assert_eq!((cpu.pc, cpu.i), (0x0000, 0x00E0));
+
+    // the last word of memory
+    cpu.pc = 0xFFFE;
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.pc, 0x0000);
+}
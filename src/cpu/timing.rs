@@ -0,0 +1,127 @@
+//! How long instructions take on the COSMAC VIP.
+//!
+//! The VIP's CDP1802 runs a machine cycle every 8 clocks of its 1.76MHz crystal,
+//! about 3668 machine cycles per 60Hz frame. The CDP1861 video chip steals one of
+//! them for every byte it displays, and the interrupt routine that ticks the timers
+//! takes a few more, leaving the interpreter with the rest.
+//!
+//! The costs below follow the interpreter's routines: a fixed fetch and decode,
+//! then the time taken by the routine of each instruction, which for a few of them
+//! depends on their operands. SUPER-CHIP and XO-CHIP instructions don't exist on the
+//! VIP and only cost the fetch.
+
+use super::{AddressingMode, Instruction, Opcode, CPU};
+use crate::bus::Bus;
+
+/// Machine cycles per 60Hz frame
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+// the 8 bytes of each of the 128 lines displayed are read by DMA
+const DISPLAY_DMA_CYCLES: u32 = 1024;
+// the interrupt routine, which also ticks the timers
+const INTERRUPT_CYCLES: u32 = 46;
+
+/// Machine cycles left for the interpreter in every frame
+pub const VIP_INTERPRETER_CYCLES: u32 =
+    VIP_CYCLES_PER_FRAME - DISPLAY_DMA_CYCLES - INTERRUPT_CYCLES;
+
+// fetching an instruction and jumping to its routine
+const FETCH_CYCLES: u32 = 40;
+// taking a conditional skip
+const SKIP_CYCLES: u32 = 4;
+
+impl<B: Bus> CPU<B> {
+    /// Machine cycles the VIP interpreter would take to run `instruction` from the
+    /// current state, fetch included
+    pub fn vip_cycles(&self, instruction: Instruction) -> u32 {
+        let skip = |skipped: bool| if skipped { SKIP_CYCLES } else { 0 };
+
+        let cycles = match instruction {
+            // clears the 256 bytes of the display buffer one at a time
+            Instruction(Opcode::CLS, _) => 24 + 256 * 12,
+            Instruction(Opcode::RET, _) => 10,
+            Instruction(Opcode::JP, AddressingMode::Addr(_)) => 12,
+            Instruction(Opcode::JP, AddressingMode::V0Addr(addr)) => {
+                // crossing a page takes an extra carry
+                let target = addr + u16::from(self.v[0]);
+                22 + if target >> 8 != addr >> 8 { 2 } else { 0 }
+            }
+            Instruction(Opcode::CALL, _) => 26,
+
+            Instruction(Opcode::SE, AddressingMode::VxImediate(x, kk)) => {
+                10 + skip(self.v[x] == kk)
+            }
+            Instruction(Opcode::SNE, AddressingMode::VxImediate(x, kk)) => {
+                10 + skip(self.v[x] != kk)
+            }
+            Instruction(Opcode::SE, AddressingMode::VxVy(x, y)) => {
+                14 + skip(self.v[x] == self.v[y])
+            }
+            Instruction(Opcode::SNE, AddressingMode::VxVy(x, y)) => {
+                14 + skip(self.v[x] != self.v[y])
+            }
+            Instruction(Opcode::SKP, AddressingMode::Vx(x)) => {
+                14 + skip(self.keypad.is_pressed(self.v[x]))
+            }
+            Instruction(Opcode::SKNP, AddressingMode::Vx(x)) => {
+                14 + skip(!self.keypad.is_pressed(self.v[x]))
+            }
+
+            Instruction(Opcode::LD, AddressingMode::VxImediate(..)) => 6,
+            Instruction(Opcode::ADD, AddressingMode::VxImediate(..)) => 10,
+            // the 8XYN instructions run their ALU operation from a small routine built
+            // in memory
+            Instruction(Opcode::LD, AddressingMode::VxVy(..))
+            | Instruction(Opcode::OR, _)
+            | Instruction(Opcode::AND, _)
+            | Instruction(Opcode::XOR, _)
+            | Instruction(Opcode::ADD, AddressingMode::VxVy(..))
+            | Instruction(Opcode::SUB, _)
+            | Instruction(Opcode::SHR, _)
+            | Instruction(Opcode::SUBN, _)
+            | Instruction(Opcode::SHL, _) => 44,
+
+            Instruction(Opcode::LD, AddressingMode::IAddr(_)) => 12,
+            Instruction(Opcode::RND, _) => 36,
+            Instruction(Opcode::DRW, AddressingMode::VxVyImediate(x, y, n)) => {
+                self.vip_draw_cycles(self.v[x], self.v[y], n)
+            }
+
+            Instruction(Opcode::LD, AddressingMode::VxDT(_))
+            | Instruction(Opcode::LD, AddressingMode::DTVx(_))
+            | Instruction(Opcode::LD, AddressingMode::STVx(_)) => 10,
+            // the wait for a key is spent outside of the instruction
+            Instruction(Opcode::LD, AddressingMode::VxKey(_)) => 10,
+            Instruction(Opcode::ADD, AddressingMode::IVx(_)) => 16,
+            Instruction(Opcode::LD, AddressingMode::FVx(_)) => 16,
+            // the digits are computed by repeated subtraction
+            Instruction(Opcode::LD, AddressingMode::BVx(x)) => {
+                let value = self.v[x];
+                let digits = value / 100 + value / 10 % 10 + value % 10;
+                80 + 16 * u32::from(digits)
+            }
+            Instruction(Opcode::LD, AddressingMode::MemVx(x))
+            | Instruction(Opcode::LD, AddressingMode::VxMem(x)) => 14 + 14 * (x as u32 + 1),
+
+            _ => 0,
+        };
+
+        FETCH_CYCLES + cycles
+    }
+
+    // drawing costs more for every row on the screen, and more again when the rows
+    // straddle two bytes of the display buffer
+    fn vip_draw_cycles(&self, x: u8, y: u8, n: u8) -> u32 {
+        let (width, height) = (self.bus.width(), self.bus.height());
+        let (x, y) = (usize::from(x) % width, usize::from(y) % height);
+
+        let rows = if self.quirks.clip_sprites {
+            usize::from(n).min(height - y)
+        } else {
+            usize::from(n)
+        };
+        let row_cycles = if x % 8 == 0 { 34 } else { 46 };
+
+        26 + rows as u32 * row_cycles
+    }
+}
@@ -0,0 +1,444 @@
+//! Metadata about known ROMs, looked up by the SHA-1 of the loaded program.
+//!
+//! The database is in the format of the CHIP-8 database
+//! (<https://github.com/chip-8/chip-8-database>): `programs.json` lists the programs
+//! with the hash and settings of each of their roms, and `platforms.json` the quirks
+//! and speed of each platform. A copy is bundled from `data/chip-8-database`, and
+//! [`Database::from_dir`] reads another one, like a checkout of the upstream files.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::{error, fmt, fs, io};
+
+use serde_json::{Map, Value};
+
+use crate::cpu::Quirks;
+use crate::mmu::MMU;
+
+const BUNDLED_PROGRAMS: &str = include_str!("../../data/chip-8-database/programs.json");
+const BUNDLED_PLATFORMS: &str = include_str!("../../data/chip-8-database/platforms.json");
+
+/// Errors raised while reading a database
+#[derive(Debug)]
+pub enum DatabaseError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// A field is missing or holds a value of the wrong kind
+    Invalid(&'static str),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Io(error) => write!(f, "{}", error),
+            DatabaseError::Json(error) => write!(f, "invalid json: {}", error),
+            DatabaseError::Invalid(field) => write!(f, "invalid {} in the database", field),
+        }
+    }
+}
+
+impl error::Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            DatabaseError::Io(error) => Some(error),
+            DatabaseError::Json(error) => Some(error),
+            DatabaseError::Invalid(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for DatabaseError {
+    fn from(error: io::Error) -> Self {
+        DatabaseError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(error: serde_json::Error) -> Self {
+        DatabaseError::Json(error)
+    }
+}
+
+/// A CHIP-8 implementation ROMs are written for
+#[derive(Debug, Clone, PartialEq)]
+pub struct Platform {
+    /// Identifier used by the roms, like `originalChip8` or `xochip`
+    pub id: String,
+    pub name: String,
+    /// Instructions per frame
+    pub tick_rate: Option<u32>,
+    pub quirks: Quirks,
+}
+
+/// What the database knows about a rom
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    /// Title of the program the rom belongs to
+    pub title: String,
+    /// Identifier of the platform the rom runs best on
+    pub platform: Option<String>,
+    /// Instructions per frame, from the rom or its platform
+    pub tick_rate: Option<u32>,
+    /// Quirks of the platform, with the ones the rom needs changed
+    pub quirks: Option<Quirks>,
+    /// Where the rom is loaded, when not at 0x200
+    pub start_address: Option<usize>,
+    /// RGB colours of the pixels for each combination of lit planes, background first
+    pub colors: Vec<[u8; 3]>,
+    /// Game actions (`up`, `a`...) and the key of the keypad they are on
+    pub keys: Vec<(String, u8)>,
+}
+
+impl RomInfo {
+    /// The key of the keypad for a game action
+    pub fn key(&self, action: &str) -> Option<u8> {
+        self.keys
+            .iter()
+            .find(|(name, _)| name == action)
+            .map(|&(_, key)| key)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Database {
+    platforms: HashMap<String, Platform>,
+    // rom info by lowercase SHA-1
+    roms: HashMap<String, RomInfo>,
+}
+
+impl Database {
+    /// The database bundled with the emulator
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_PROGRAMS, BUNDLED_PLATFORMS).expect("the bundled database is valid")
+    }
+
+    /// Read `programs.json` and `platforms.json` from `dir`
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self, DatabaseError> {
+        let dir = dir.as_ref();
+        let programs = fs::read_to_string(dir.join("programs.json"))?;
+        let platforms = fs::read_to_string(dir.join("platforms.json"))?;
+
+        Self::parse(&programs, &platforms)
+    }
+
+    /// Parse the content of `programs.json` and `platforms.json`
+    pub fn parse(programs: &str, platforms: &str) -> Result<Self, DatabaseError> {
+        let mut database = Self::default();
+
+        let platforms: Value = serde_json::from_str(platforms)?;
+        for platform in platforms
+            .as_array()
+            .ok_or(DatabaseError::Invalid("platforms"))?
+        {
+            let platform = parse_platform(platform)?;
+            database.platforms.insert(platform.id.clone(), platform);
+        }
+
+        let programs: Value = serde_json::from_str(programs)?;
+        for program in programs
+            .as_array()
+            .ok_or(DatabaseError::Invalid("programs"))?
+        {
+            let title = program
+                .get("title")
+                .and_then(Value::as_str)
+                .ok_or(DatabaseError::Invalid("title"))?;
+            let roms = program
+                .get("roms")
+                .and_then(Value::as_object)
+                .ok_or(DatabaseError::Invalid("roms"))?;
+
+            for (sha1, rom) in roms {
+                let info = database.parse_rom(title, rom)?;
+                database.roms.insert(sha1.to_ascii_lowercase(), info);
+            }
+        }
+
+        Ok(database)
+    }
+
+    /// The rom with the SHA-1 `sha1`, in hexadecimal
+    pub fn lookup(&self, sha1: &str) -> Option<&RomInfo> {
+        self.roms.get(&sha1.to_ascii_lowercase())
+    }
+
+    /// The rom loaded in `mmu`
+    pub fn find(&self, mmu: &MMU) -> Option<&RomInfo> {
+        self.lookup(&mmu.program_sha1())
+    }
+
+    pub fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.get(id)
+    }
+
+    // number of roms known
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    fn parse_rom(&self, title: &str, rom: &Value) -> Result<RomInfo, DatabaseError> {
+        let platform = match rom.get("platforms") {
+            Some(platforms) => {
+                let platforms = platforms
+                    .as_array()
+                    .ok_or(DatabaseError::Invalid("platforms"))?;
+                match platforms.first() {
+                    Some(id) => Some(id.as_str().ok_or(DatabaseError::Invalid("platforms"))?),
+                    None => None,
+                }
+            }
+            None => None,
+        };
+        let known_platform = platform.and_then(|id| self.platforms.get(id));
+
+        // quirks of the platform, changed for this rom
+        let quirks = match known_platform {
+            Some(known_platform) => {
+                let mut quirks = known_platform.quirks;
+                let changed = rom
+                    .get("quirkyPlatforms")
+                    .and_then(|quirky| quirky.get(&known_platform.id));
+                if let Some(changed) = changed {
+                    let changed = changed
+                        .as_object()
+                        .ok_or(DatabaseError::Invalid("quirkyPlatforms"))?;
+                    apply_quirks(&mut quirks, changed)?;
+                }
+                Some(quirks)
+            }
+            None => None,
+        };
+
+        let tick_rate = match rom.get("tickrate") {
+            Some(tick_rate) => Some(as_u32(tick_rate, "tickrate")?),
+            None => known_platform.and_then(|platform| platform.tick_rate),
+        };
+
+        let start_address = match rom.get("startAddress") {
+            Some(address) => Some(as_u32(address, "startAddress")? as usize),
+            None => None,
+        };
+
+        let mut colors = Vec::new();
+        if let Some(pixels) = rom.get("colors").and_then(|colors| colors.get("pixels")) {
+            for color in pixels.as_array().ok_or(DatabaseError::Invalid("colors"))? {
+                let color = color.as_str().and_then(parse_color);
+                colors.push(color.ok_or(DatabaseError::Invalid("colors"))?);
+            }
+        }
+
+        let mut keys = Vec::new();
+        if let Some(mapping) = rom.get("keys") {
+            for (action, key) in mapping.as_object().ok_or(DatabaseError::Invalid("keys"))? {
+                match key.as_u64() {
+                    Some(key) if key <= 0xF => keys.push((action.clone(), key as u8)),
+                    _ => return Err(DatabaseError::Invalid("keys")),
+                }
+            }
+        }
+
+        Ok(RomInfo {
+            title: title.to_string(),
+            platform: platform.map(String::from),
+            tick_rate,
+            quirks,
+            start_address,
+            colors,
+            keys,
+        })
+    }
+}
+
+fn as_u32(value: &Value, field: &'static str) -> Result<u32, DatabaseError> {
+    value
+        .as_u64()
+        .and_then(|value| u32::try_from(value).ok())
+        .ok_or(DatabaseError::Invalid(field))
+}
+
+fn parse_platform(platform: &Value) -> Result<Platform, DatabaseError> {
+    let field = |name: &'static str| {
+        platform
+            .get(name)
+            .and_then(Value::as_str)
+            .map(String::from)
+            .ok_or(DatabaseError::Invalid(name))
+    };
+
+    let mut quirks = Quirks::cosmac_vip();
+    if let Some(changed) = platform.get("quirks") {
+        apply_quirks(
+            &mut quirks,
+            changed
+                .as_object()
+                .ok_or(DatabaseError::Invalid("quirks"))?,
+        )?;
+    }
+
+    Ok(Platform {
+        id: field("id")?,
+        name: field("name")?,
+        tick_rate: match platform.get("defaultTickrate") {
+            Some(tick_rate) => Some(as_u32(tick_rate, "defaultTickrate")?),
+            None => None,
+        },
+        quirks,
+    })
+}
+
+// set the quirks named in `changed`, where `true` means the behaviour of later platforms
+fn apply_quirks(quirks: &mut Quirks, changed: &Map<String, Value>) -> Result<(), DatabaseError> {
+    for (name, value) in changed {
+        let value = value.as_bool().ok_or(DatabaseError::Invalid("quirks"))?;
+        match name.as_str() {
+            "shift" => quirks.shift_uses_vy = !value,
+            "memoryIncrementByX" => quirks.increment_i_by_x = value,
+            "memoryLeaveIUnchanged" => quirks.load_store_increments_i = !value,
+            "wrap" => quirks.clip_sprites = !value,
+            "jump" => quirks.jump_with_vx = value,
+            "vblank" => quirks.display_wait = value,
+            "logic" => quirks.vf_reset = value,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// a `#rrggbb` colour
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn loaded(program: &[u8]) -> MMU {
+        let mut mmu = MMU::default();
+        mmu.load_rom_bytes(program).unwrap();
+        mmu
+    }
+
+    fn database(sha1: &str, rom: &str) -> Database {
+        let programs = format!(
+            r#"[{{ "title": "Test", "roms": {{ "{}": {} }} }}]"#,
+            sha1, rom
+        );
+        Database::parse(&programs, BUNDLED_PLATFORMS).unwrap()
+    }
+
+    #[test]
+    fn bundled_database_parses() {
+        let database = Database::bundled();
+
+        assert_eq!(
+            database.platform("originalChip8").unwrap().quirks,
+            Quirks::cosmac_vip()
+        );
+        assert_eq!(
+            database.platform("chip48").unwrap().quirks,
+            Quirks::chip48()
+        );
+        assert_eq!(
+            database.platform("superchip").unwrap().quirks,
+            Quirks::superchip()
+        );
+        assert_eq!(
+            database.platform("xochip").unwrap().quirks,
+            Quirks::xo_chip()
+        );
+    }
+
+    #[test]
+    fn bundled_database_knows_the_test_roms() {
+        let database = Database::bundled();
+        let roms = [
+            ("opcodes.asm", "Opcode test"),
+            ("flags.asm", "Flags test"),
+            ("quirks.asm", "Quirks test"),
+            ("keypad.asm", "Keypad test"),
+        ];
+
+        for &(rom, title) in &roms {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/roms")
+                .join(rom);
+            let program = Assembler::new().assemble_file(&path).unwrap();
+
+            let info = database.find(&loaded(&program)).unwrap();
+            assert_eq!(info.title, title);
+            assert_eq!(info.platform.as_deref(), Some("originalChip8"));
+            assert_eq!(info.quirks, Some(Quirks::cosmac_vip()));
+            assert_eq!(info.tick_rate, Some(20));
+        }
+    }
+
+    #[test]
+    fn roms_are_found_by_the_hash_of_the_program() {
+        let mmu = loaded(&[0x12, 0x00]);
+        let sha1 = mmu.program_sha1();
+        let database = database(&sha1.to_uppercase(), r#"{ "platforms": ["superchip"] }"#);
+
+        let info = database.find(&mmu).unwrap();
+        assert_eq!(info.title, "Test");
+        assert_eq!(info.platform.as_deref(), Some("superchip"));
+        assert_eq!(info.quirks, Some(Quirks::superchip()));
+        assert_eq!(info.tick_rate, Some(30));
+
+        assert!(database.find(&loaded(&[0x12, 0x02])).is_none());
+    }
+
+    #[test]
+    fn roms_change_the_quirks_and_settings_of_their_platform() {
+        let rom = r##"{
+            "platforms": ["originalChip8"],
+            "quirkyPlatforms": { "originalChip8": { "vblank": false, "shift": true } },
+            "tickrate": 40,
+            "startAddress": 1536,
+            "colors": { "pixels": ["#000000", "#FFaa00"] },
+            "keys": { "up": 5, "a": 15 }
+        }"##;
+        let database = database("da39a3ee5e6b4b0d3255bfef95601890afd80709", rom);
+        let info = database
+            .lookup("da39a3ee5e6b4b0d3255bfef95601890afd80709")
+            .unwrap();
+
+        let quirks = info.quirks.unwrap();
+        assert!(!quirks.display_wait);
+        assert!(!quirks.shift_uses_vy);
+        assert!(quirks.vf_reset);
+        assert_eq!(info.tick_rate, Some(40));
+        assert_eq!(info.start_address, Some(0x600));
+        assert_eq!(info.colors, vec![[0, 0, 0], [0xFF, 0xAA, 0x00]]);
+        assert_eq!(
+            (info.key("up"), info.key("a"), info.key("b")),
+            (Some(5), Some(15), None)
+        );
+    }
+
+    #[test]
+    fn invalid_fields_are_reported() {
+        let programs = r#"[{ "title": "Test", "roms": { "00": { "keys": { "up": 16 } } } }]"#;
+
+        assert!(matches!(
+            Database::parse(programs, BUNDLED_PLATFORMS),
+            Err(DatabaseError::Invalid("keys"))
+        ));
+        assert!(matches!(
+            Database::parse("{", BUNDLED_PLATFORMS),
+            Err(DatabaseError::Json(_))
+        ));
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::parse_hex;
+use crate::cpu::CPU;
+
+/// A register a condition can test
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    V(usize),
+    I,
+    PC,
+    SP,
+    DT,
+    ST,
+}
+
+impl Register {
+    pub fn read(self, cpu: &CPU) -> u16 {
+        match self {
+            Register::V(x) => u16::from(cpu.v[x]),
+            Register::I => cpu.i,
+            Register::PC => cpu.pc,
+            Register::SP => cpu.sp,
+            Register::DT => u16::from(cpu.delay),
+            Register::ST => u16::from(cpu.sound),
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "i" => Ok(Register::I),
+            "pc" => Ok(Register::PC),
+            "sp" => Ok(Register::SP),
+            "dt" => Ok(Register::DT),
+            "st" => Ok(Register::ST),
+            name => name
+                .strip_prefix('v')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| usize::from_str_radix(x, 16).ok())
+                .map(Register::V)
+                .ok_or(format!("unknown register: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::PC => write!(f, "PC"),
+            Register::SP => write!(f, "SP"),
+            Register::DT => write!(f, "DT"),
+            Register::ST => write!(f, "ST"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    const SYMBOLS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+
+    pub fn compare(self, lhs: u16, rhs: u16) -> bool {
+        match self {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        Self::SYMBOLS
+            .iter()
+            .find(|&&(_, comparison)| comparison == self)
+            .map(|&(symbol, _)| symbol)
+            .unwrap()
+    }
+}
+
+/// Compares a register with a value, like `V3 == 2A` or `I >= 300` (values in hex)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        self.comparison.compare(self.register.read(cpu), self.value)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (symbol, comparison) = Comparison::SYMBOLS
+            .iter()
+            .find(|(symbol, _)| s.contains(symbol))
+            .ok_or(format!("missing a comparison in: {}", s))?;
+
+        let mut parts = s.splitn(2, symbol);
+        let register = parts.next().unwrap_or_default().trim().parse()?;
+        let value = parse_hex(parts.next().unwrap_or_default().trim())?;
+
+        Ok(Self {
+            register,
+            comparison: *comparison,
+            value,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:X}",
+            self.register,
+            self.comparison.symbol(),
+            self.value
+        )
+    }
+}
@@ -0,0 +1,440 @@
+//! Breakpoints, watchpoints and single stepping around a [`Machine`].
+//!
+//! The debugger runs the machine one instruction at a time, so the timers keep
+//! ticking every frame as they would while the game runs normally.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::cpu::{CpuError, Opcode, Status};
+use crate::machine::Machine;
+use crate::mmu::Access;
+
+mod condition;
+mod repl;
+
+pub use condition::{Comparison, Condition, Register};
+pub use repl::{Repl, Response};
+
+/// How many instructions `continue` and friends run before giving up
+pub const DEFAULT_RUN_LIMIT: u64 = 10_000_000;
+
+// parse a hex number, with an optional `0x` or `#` prefix
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix('#'))
+        .unwrap_or(s);
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex number: {}", s))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+/// Stops when an instruction reads or writes memory within `start..=end`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub end: usize,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, access: Access) -> bool {
+        let (offset, kind) = match access {
+            Access::Read(offset) => (offset, WatchKind::Read),
+            Access::Write(offset, _) => (offset, WatchKind::Write),
+        };
+
+        (self.start..=self.end).contains(&offset)
+            && (self.kind == kind || self.kind == WatchKind::Access)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+        };
+
+        if self.start == self.end {
+            write!(f, "{} {:03X}", kind, self.start)
+        } else {
+            write!(f, "{} {:03X}-{:03X}", kind, self.start, self.end)
+        }
+    }
+}
+
+/// Why the debugger gave the control back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// The step finished
+    Done,
+    /// About to execute the instruction at this address
+    Breakpoint(u16),
+    /// The watchpoint at this index saw the access
+    Watchpoint(usize, Access),
+    /// The condition at this index became true
+    Condition(usize),
+    /// The cpu halted, faulted or waits for a key, it won't go on by itself
+    Blocked(Status),
+    Fault(CpuError),
+    /// Ran the maximum number of instructions without stopping
+    Limit,
+    /// Stepping out with no subroutine to return from
+    NoSubroutine,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Done => write!(f, "done"),
+            Stop::Breakpoint(pc) => write!(f, "breakpoint at {:03X}", pc),
+            Stop::Watchpoint(n, Access::Read(offset)) => {
+                write!(f, "watchpoint {}: read {:03X}", n, offset)
+            }
+            Stop::Watchpoint(n, Access::Write(offset, byte)) => {
+                write!(f, "watchpoint {}: wrote {:02X} to {:03X}", n, byte, offset)
+            }
+            Stop::Condition(n) => write!(f, "condition {} is true", n),
+            Stop::Blocked(Status::WaitingKeypress(x)) => {
+                write!(f, "waiting for a key to load in V{:X}", x)
+            }
+            Stop::Blocked(Status::Faulted { pc, opcode }) => {
+                write!(f, "cpu faulted on {:04X} at {:03X}", opcode, pc)
+            }
+            Stop::Blocked(status) => write!(f, "cpu is {:?}", status),
+            Stop::Fault(error) => write!(f, "cpu fault: {}", error),
+            Stop::Limit => write!(f, "stopped after too many instructions"),
+            Stop::NoSubroutine => write!(f, "not in a subroutine"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    // each condition along with whether it held after the last instruction, so
+    // it only stops the machine when it becomes true
+    conditions: Vec<(Condition, bool)>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns false if there was already a breakpoint at `pc`
+    pub fn add_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.insert(pc)
+    }
+    pub fn remove_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.watchpoints.len() {
+            Some(self.watchpoints.remove(index))
+        } else {
+            None
+        }
+    }
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_condition(&mut self, machine: &Machine, condition: Condition) -> usize {
+        let holds = condition.holds(&machine.cpu);
+        self.conditions.push((condition, holds));
+        self.conditions.len() - 1
+    }
+    pub fn remove_condition(&mut self, index: usize) -> Option<Condition> {
+        if index < self.conditions.len() {
+            Some(self.conditions.remove(index).0)
+        } else {
+            None
+        }
+    }
+    pub fn conditions(&self) -> impl Iterator<Item = &Condition> + '_ {
+        self.conditions.iter().map(|(condition, _)| condition)
+    }
+
+    // run a single instruction, checking the watchpoints and conditions
+    fn execute(&mut self, machine: &mut Machine) -> Option<Stop> {
+        machine.cpu.bus.log_accesses(true);
+        let result = machine.step();
+        let accesses = machine.cpu.bus.take_accesses();
+
+        if let Err(error) = result {
+            return Some(Stop::Fault(error));
+        }
+
+        let mut stop = accesses.into_iter().find_map(|access| {
+            self.watchpoints
+                .iter()
+                .position(|watchpoint| watchpoint.matches(access))
+                .map(|n| Stop::Watchpoint(n, access))
+        });
+
+        // update every condition, even when the first one already stops the machine
+        for (n, (condition, held)) in self.conditions.iter_mut().enumerate() {
+            let holds = condition.holds(&machine.cpu);
+            if holds && !*held && stop.is_none() {
+                stop = Some(Stop::Condition(n));
+            }
+            *held = holds;
+        }
+
+        // blocked by this instruction or from before it, like a key still awaited
+        match machine.cpu.status() {
+            status @ Status::Halt
+            | status @ Status::WaitingKeypress(_)
+            | status @ Status::Faulted { .. } => stop.or(Some(Stop::Blocked(status))),
+            _ => stop,
+        }
+    }
+
+    // run until `done` holds or something stops the machine. The breakpoint at
+    // the current instruction is skipped, so it's possible to resume from it
+    fn run_until<F>(&mut self, machine: &mut Machine, limit: u64, done: F) -> Stop
+    where
+        F: Fn(&Machine) -> bool,
+    {
+        for n in 0..limit {
+            let pc = machine.cpu.pc;
+            if n > 0 && self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+
+            if let Some(stop) = self.execute(machine) {
+                return stop;
+            }
+            if done(machine) {
+                return Stop::Done;
+            }
+        }
+
+        Stop::Limit
+    }
+
+    /// Run a single instruction
+    pub fn step(&mut self, machine: &mut Machine) -> Stop {
+        self.run_until(machine, 1, |_| true)
+    }
+
+    /// Run a single instruction, running a whole subroutine when it is a CALL
+    pub fn step_over(&mut self, machine: &mut Machine, limit: u64) -> Stop {
+        let cpu = &machine.cpu;
+        match cpu.peek(cpu.pc) {
+            Ok(instruction) if instruction.0 == Opcode::CALL => {
+                let (sp, next) = (cpu.sp, cpu.pc.wrapping_add(instruction.size()));
+                self.run_until(machine, limit, |machine| {
+                    machine.cpu.sp == sp && machine.cpu.pc == next
+                })
+            }
+            _ => self.step(machine),
+        }
+    }
+
+    /// Run until the current subroutine returns
+    pub fn step_out(&mut self, machine: &mut Machine, limit: u64) -> Stop {
+        let sp = machine.cpu.sp;
+        if sp == 0 {
+            return Stop::NoSubroutine;
+        }
+        self.run_until(machine, limit, |machine| machine.cpu.sp < sp)
+    }
+
+    /// Run until a breakpoint, watchpoint or condition stops the machine
+    pub fn resume(&mut self, machine: &mut Machine, limit: u64) -> Stop {
+        self.run_until(machine, limit, |_| false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Quirks, CPU};
+    use crate::mmu::MMU;
+
+    fn machine(program: &[u8]) -> Machine {
+        let mut mmu = MMU::default();
+        for (i, &byte) in program.iter().enumerate() {
+            mmu.wb(0x200 + i, byte).unwrap();
+        }
+        mmu.lock_rom();
+
+        Machine::new(CPU::new(mmu, Quirks::default()))
+    }
+
+    // 200: CALL 208; 202: LD I, 300; 204: LD [I], V0; 206: JP 206
+    // 208: ADD V0, 1; 20A: RET
+    const PROGRAM: [u8; 12] = [
+        0x22, 0x08, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06, 0x70, 0x01, 0x00, 0xEE,
+    ];
+
+    #[test]
+    fn stops_at_breakpoints_and_resumes_from_them() {
+        let mut machine = machine(&PROGRAM);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x208);
+        debugger.add_breakpoint(0x204);
+
+        assert_eq!(debugger.resume(&mut machine, 100), Stop::Breakpoint(0x208));
+        assert_eq!(debugger.resume(&mut machine, 100), Stop::Breakpoint(0x204));
+        assert_eq!(debugger.resume(&mut machine, 100), Stop::Limit);
+    }
+
+    #[test]
+    fn steps_over_and_out_of_subroutines() {
+        let mut machine = machine(&PROGRAM);
+        let mut debugger = Debugger::new();
+
+        assert_eq!(debugger.step_over(&mut machine, 100), Stop::Done);
+        assert_eq!((machine.cpu.pc, machine.cpu.v[0]), (0x202, 1));
+
+        let mut machine = self::machine(&PROGRAM);
+        assert_eq!(debugger.step(&mut machine), Stop::Done);
+        assert_eq!(machine.cpu.pc, 0x208);
+        assert_eq!(debugger.step_out(&mut machine, 100), Stop::Done);
+        assert_eq!((machine.cpu.pc, machine.cpu.sp), (0x202, 0));
+        assert_eq!(debugger.step_out(&mut machine, 100), Stop::NoSubroutine);
+        assert_eq!(machine.cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn watchpoints_see_data_accesses_only() {
+        let mut machine = machine(&PROGRAM);
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(Watchpoint {
+            start: 0x200,
+            end: 0x20B,
+            kind: WatchKind::Access,
+        });
+        let n = debugger.add_watchpoint(Watchpoint {
+            start: 0x300,
+            end: 0x300,
+            kind: WatchKind::Write,
+        });
+
+        assert_eq!(
+            debugger.resume(&mut machine, 100),
+            Stop::Watchpoint(n, Access::Write(0x300, 1))
+        );
+        assert_eq!(machine.cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn conditions_stop_when_they_become_true() {
+        // ADD V0, 1; JP 200
+        let mut machine = machine(&[0x70, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new();
+        debugger.add_condition(&machine, "v0 >= #3".parse().unwrap());
+
+        assert_eq!(debugger.resume(&mut machine, 100), Stop::Condition(0));
+        assert_eq!(machine.cpu.v[0], 3);
+
+        // it stays true until V0 wraps around
+        assert_eq!(debugger.resume(&mut machine, 1000), Stop::Condition(0));
+        assert_eq!(machine.cpu.v[0], 3);
+    }
+
+    #[test]
+    fn memory_dumps_take_decimal_lengths() {
+        let mut machine = machine(&PROGRAM);
+        let mut repl = Repl::default();
+
+        assert_eq!(
+            repl.execute(&mut machine, "x 200 10"),
+            Ok(Response::Output(
+                "200: 22 08 A3 00 F0 55 12 06 70 01".to_string()
+            ))
+        );
+        match repl.execute(&mut machine, "x 200") {
+            Ok(Response::Output(dump)) => assert_eq!(dump.lines().count(), 4),
+            other => panic!("unexpected response: {:?}", other),
+        }
+        match repl.execute(&mut machine, "x FF0 18446744073709551615") {
+            Ok(Response::Output(dump)) => assert_eq!(dump.lines().count(), 1),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn removing_needs_the_watchpoint_or_condition() {
+        let mut machine = machine(&PROGRAM);
+        let mut repl = Repl::default();
+
+        assert_eq!(
+            repl.execute(&mut machine, "unwatch"),
+            Err("usage: unwatch N".to_string())
+        );
+        assert_eq!(
+            repl.execute(&mut machine, "unwhen"),
+            Err("usage: unwhen N".to_string())
+        );
+        assert_eq!(
+            repl.execute(&mut machine, "unwatch 0"),
+            Err("no watchpoint 0".to_string())
+        );
+    }
+
+    #[test]
+    fn listings_stop_at_the_end_of_memory() {
+        let mut mmu = MMU::xo_chip();
+        mmu.lock_rom();
+        let mut machine = Machine::new(CPU::new(mmu, Quirks::xo_chip()));
+        machine.cpu.pc = 0xFFFC;
+
+        match Repl::default().execute(&mut machine, "l") {
+            Ok(Response::Output(listing)) => assert_eq!(listing.lines().count(), 2),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resuming_a_blocked_cpu_stops_right_away() {
+        // 200: LD V0, K; 202: JP 202
+        let mut machine = machine(&[0xF0, 0x0A, 0x12, 0x02]);
+        let mut debugger = Debugger::new();
+        let blocked = Stop::Blocked(Status::WaitingKeypress(0));
+
+        assert_eq!(debugger.resume(&mut machine, 100), blocked);
+        assert_eq!(debugger.resume(&mut machine, 100), blocked);
+
+        machine.cpu.keypad.press(0x5);
+        machine.cpu.keypad.release(0x5);
+        assert_eq!(debugger.resume(&mut machine, 100), Stop::Limit);
+        assert_eq!(machine.cpu.v[0], 0x5);
+    }
+
+    #[test]
+    fn resuming_a_faulted_cpu_stops_right_away() {
+        // 200: an invalid opcode
+        let mut machine = machine(&[0x50, 0x01]);
+        let mut debugger = Debugger::new();
+        let blocked = Stop::Blocked(Status::Faulted {
+            pc: 0x200,
+            opcode: 0x5001,
+        });
+
+        assert!(matches!(debugger.step(&mut machine), Stop::Fault(_)));
+        assert_eq!(debugger.step(&mut machine), blocked);
+        assert_eq!(debugger.resume(&mut machine, 100), blocked);
+    }
+}
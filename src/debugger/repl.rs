@@ -0,0 +1,318 @@
+use std::fmt::Write;
+
+use super::{parse_hex, Debugger, Stop, WatchKind, Watchpoint, DEFAULT_RUN_LIMIT};
+use crate::machine::Machine;
+
+const HELP: &str = "Addresses and values are in hex, counts in decimal.
+
+  s, step [N]            run N instructions (default: 1)
+  n, next                step over subroutine calls
+  o, out                 run until the current subroutine returns
+  c, continue            run until something stops the machine
+  b, break ADDR          stop before running the instruction at ADDR
+  d, delete ADDR         remove the breakpoint at ADDR
+  w, watch [read|write|access] ADDR[-END]
+                         stop when memory is read or written
+  unwatch N              remove the watchpoint N
+  when REG OP VALUE      stop when a condition becomes true, like `when v3 == 2A`
+                         (REG is V0-VF, I, PC, SP, DT or ST, OP is ==, !=, <, <=, > or >=)
+  unwhen N               remove the condition N
+  info                   list the breakpoints, watchpoints and conditions
+  r, regs                show the registers
+  l, list [N]            show the next N instructions (default: 8)
+  x, mem ADDR [LEN]      dump LEN bytes of memory (default: 64)
+  screen                 show the screen
+  press KEY, release KEY press or release a key of the keypad
+  q, quit                exit
+
+An empty line repeats the last command.";
+
+/// What the front-end should do after a command
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    Output(String),
+    Quit,
+}
+
+/// Text commands driving a [`Debugger`], for terminal front-ends
+#[derive(Default)]
+pub struct Repl {
+    pub debugger: Debugger,
+    last_command: String,
+}
+
+fn parse_count(s: Option<&str>, default: usize) -> Result<usize, String> {
+    s.map_or(Ok(default), |s| {
+        s.parse().map_err(|_| format!("invalid count: {}", s))
+    })
+}
+
+fn parse_key(s: Option<&str>) -> Result<u8, String> {
+    let s = s.ok_or("missing the key")?;
+    match parse_hex(s)? {
+        key @ 0..=0xF => Ok(key as u8),
+        _ => Err(format!("invalid key: {}", s)),
+    }
+}
+
+fn parse_range(s: &str) -> Result<(usize, usize), String> {
+    let mut bounds = s.splitn(2, '-');
+    let start = parse_hex(bounds.next().unwrap_or_default())?;
+    let end = bounds.next().map_or(Ok(start), parse_hex)?;
+
+    if end < start {
+        return Err(format!("invalid range: {}", s));
+    }
+    Ok((usize::from(start), usize::from(end)))
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn help() -> &'static str {
+        HELP
+    }
+
+    /// Run a command line, an empty one repeats the last command
+    pub fn execute(&mut self, machine: &mut Machine, line: &str) -> Result<Response, String> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command = line.clone();
+
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(Response::Output(String::new())),
+        };
+        let mut arg = || words.next();
+
+        let output = match command {
+            "s" | "step" => {
+                let count = parse_count(arg(), 1)?;
+                let mut stop = Stop::Done;
+                for _ in 0..count {
+                    stop = self.debugger.step(machine);
+                    if stop != Stop::Done {
+                        break;
+                    }
+                }
+                self.report(machine, stop)
+            }
+            "n" | "next" => {
+                let stop = self.debugger.step_over(machine, DEFAULT_RUN_LIMIT);
+                self.report(machine, stop)
+            }
+            "o" | "out" => {
+                let stop = self.debugger.step_out(machine, DEFAULT_RUN_LIMIT);
+                self.report(machine, stop)
+            }
+            "c" | "continue" => {
+                let stop = self.debugger.resume(machine, DEFAULT_RUN_LIMIT);
+                self.report(machine, stop)
+            }
+            "b" | "break" => {
+                let pc = parse_hex(arg().ok_or("missing the address")?)?;
+                self.debugger.add_breakpoint(pc);
+                format!("breakpoint at {:03X}", pc)
+            }
+            "d" | "delete" => {
+                let pc = parse_hex(arg().ok_or("missing the address")?)?;
+                if !self.debugger.remove_breakpoint(pc) {
+                    return Err(format!("no breakpoint at {:03X}", pc));
+                }
+                format!("removed the breakpoint at {:03X}", pc)
+            }
+            "w" | "watch" => {
+                let (kind, range) = match (arg(), arg()) {
+                    (Some("read"), Some(range)) => (WatchKind::Read, range),
+                    (Some("write"), Some(range)) => (WatchKind::Write, range),
+                    (Some("access"), Some(range)) | (Some(range), None) => {
+                        (WatchKind::Access, range)
+                    }
+                    _ => return Err("usage: watch [read|write|access] ADDR[-END]".to_string()),
+                };
+                let (start, end) = parse_range(range)?;
+
+                let watchpoint = Watchpoint { start, end, kind };
+                let n = self.debugger.add_watchpoint(watchpoint);
+                format!("watchpoint {}: {}", n, watchpoint)
+            }
+            "unwatch" => {
+                let n = arg().ok_or("usage: unwatch N")?;
+                let n = parse_count(Some(n), 0)?;
+                let watchpoint = self
+                    .debugger
+                    .remove_watchpoint(n)
+                    .ok_or(format!("no watchpoint {}", n))?;
+                format!("removed watchpoint {}: {}", n, watchpoint)
+            }
+            "when" => {
+                let condition = line[command.len()..].trim().parse()?;
+                let n = self.debugger.add_condition(machine, condition);
+                format!("condition {}: {}", n, condition)
+            }
+            "unwhen" => {
+                let n = arg().ok_or("usage: unwhen N")?;
+                let n = parse_count(Some(n), 0)?;
+                let condition = self
+                    .debugger
+                    .remove_condition(n)
+                    .ok_or(format!("no condition {}", n))?;
+                format!("removed condition {}: {}", n, condition)
+            }
+            "info" => self.info(),
+            "r" | "regs" => registers(machine),
+            "l" | "list" => list(machine, parse_count(arg(), 8)?),
+            "x" | "mem" => {
+                let start = usize::from(parse_hex(arg().ok_or("missing the address")?)?);
+                let len = parse_count(arg(), 64)?;
+                memory(machine, start, len)
+            }
+            "screen" => screen(machine),
+            "press" => {
+                let key = parse_key(arg())?;
+                machine.cpu.keypad.press(key);
+                format!("pressed {:X}", key)
+            }
+            "release" => {
+                let key = parse_key(arg())?;
+                machine.cpu.keypad.release(key);
+                format!("released {:X}", key)
+            }
+            "h" | "help" => HELP.to_string(),
+            "q" | "quit" => return Ok(Response::Quit),
+            _ => return Err(format!("unknown command: {} (try `help`)", command)),
+        };
+
+        Ok(Response::Output(output))
+    }
+
+    // why the machine stopped, followed by the next instruction
+    fn report(&self, machine: &Machine, stop: Stop) -> String {
+        let next = list(machine, 1);
+        match stop {
+            Stop::Done => next,
+            stop => format!("{}\n{}", stop, next),
+        }
+    }
+
+    fn info(&self) -> String {
+        let mut out = String::new();
+
+        for pc in self.debugger.breakpoints() {
+            writeln!(out, "breakpoint at {:03X}", pc).unwrap();
+        }
+        for (n, watchpoint) in self.debugger.watchpoints().iter().enumerate() {
+            writeln!(out, "watchpoint {}: {}", n, watchpoint).unwrap();
+        }
+        for (n, condition) in self.debugger.conditions().enumerate() {
+            writeln!(out, "condition {}: {}", n, condition).unwrap();
+        }
+
+        if out.is_empty() {
+            "nothing set".to_string()
+        } else {
+            out.trim_end().to_string()
+        }
+    }
+}
+
+fn registers(machine: &Machine) -> String {
+    let cpu = &machine.cpu;
+    let mut out = String::new();
+
+    for (x, v) in cpu.v.iter().enumerate() {
+        let separator = if x % 8 == 7 { '\n' } else { ' ' };
+        write!(out, "V{:X}={:02X}{}", x, v, separator).unwrap();
+    }
+    writeln!(
+        out,
+        "PC={:03X} I={:03X} SP={:X} DT={:02X} ST={:02X} frame={}",
+        cpu.pc,
+        cpu.i,
+        cpu.sp,
+        cpu.delay,
+        cpu.sound,
+        machine.frames()
+    )
+    .unwrap();
+
+    let stack: Vec<String> = cpu.stack[..usize::from(cpu.sp)]
+        .iter()
+        .map(|addr| format!("{:03X}", addr))
+        .collect();
+    write!(out, "stack=[{}] status={:?}", stack.join(" "), cpu.status()).unwrap();
+
+    out
+}
+
+// the next instructions, in a straight line from the program counter
+fn list(machine: &Machine, count: usize) -> String {
+    let cpu = &machine.cpu;
+    let mut lines = Vec::new();
+
+    let mut addr = cpu.pc;
+    for _ in 0..count {
+        let word = cpu.bus.fetch(usize::from(addr));
+        let (line, size) = match (word, cpu.peek(addr)) {
+            (Ok(word), Ok(instruction)) => (
+                format!("{:03X}: {:04X}  {}", addr, word, instruction),
+                instruction.size(),
+            ),
+            (Ok(word), Err(_)) => (format!("{:03X}: {:04X}  ???", addr, word), 2),
+            (Err(error), _) => {
+                lines.push(format!("{:03X}: {}", addr, error));
+                break;
+            }
+        };
+        lines.push(line);
+
+        // stop at the end of memory
+        match addr.checked_add(size) {
+            Some(next) => addr = next,
+            None => break,
+        }
+    }
+
+    lines.join("\n")
+}
+
+fn memory(machine: &Machine, start: usize, len: usize) -> String {
+    let bus = &machine.cpu.bus;
+    let end = start.saturating_add(len).min(bus.memory_size());
+    let mut lines = Vec::new();
+
+    for row in (start..end).step_by(16) {
+        let bytes: Vec<String> = (row..(row + 16).min(end))
+            .map_while(|offset| bus.peek(offset).ok())
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        if bytes.is_empty() {
+            break;
+        }
+        lines.push(format!("{:03X}: {}", row, bytes.join(" ")));
+    }
+
+    lines.join("\n")
+}
+
+fn screen(machine: &Machine) -> String {
+    const CHARS: [char; 4] = ['.', '#', '+', '@'];
+
+    let bus = &machine.cpu.bus;
+    let (width, height) = (bus.width(), bus.height());
+
+    bus.vram[..width * height]
+        .chunks(width)
+        .map(|row| {
+            row.iter()
+                .map(|&pixel| CHARS[usize::from(pixel & 0b11)])
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,359 @@
+//! Turns a ROM back into mnemonics.
+//!
+//! The program is walked from its entry point, following jumps, calls and skips, so
+//! only the bytes the CPU can actually execute are decoded as instructions. Anything
+//! else (sprites, tables, unreachable code) is kept as raw `db` data. Computed jumps
+//! (`JP V0, addr`) can't be followed and the code they reach shows up as data.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::cpu::{AddressingMode, Instruction, Opcode, LONG_LOAD};
+
+/// Where CHIP-8 programs are loaded and start running
+pub const PROGRAM_START: u16 = 0x200;
+
+// data bytes per `db` line
+const DATA_PER_LINE: usize = 8;
+
+/// A line of the disassembly
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Label(String),
+    Code {
+        addr: u16,
+        bytes: Vec<u8>,
+        instruction: Instruction,
+    },
+    Data {
+        addr: u16,
+        bytes: Vec<u8>,
+    },
+}
+
+pub struct Disassembly {
+    origin: u16,
+    rom: Vec<u8>,
+    // the instructions reachable from the entry point, by address
+    code: BTreeMap<u16, Instruction>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl Disassembly {
+    /// Disassemble `rom`, loaded and starting at `origin`
+    pub fn new(rom: &[u8], origin: u16) -> Self {
+        // whatever doesn't fit in the address space can't be reached
+        let size = rom.len().min(0x10000 - usize::from(origin));
+
+        let mut disassembly = Self {
+            origin,
+            rom: rom[..size].to_vec(),
+            code: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembly.trace(origin);
+
+        disassembly
+    }
+
+    fn end(&self) -> usize {
+        usize::from(self.origin) + self.rom.len()
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        (usize::from(self.origin)..self.end()).contains(&usize::from(addr))
+    }
+
+    fn word(&self, addr: u16) -> Option<u16> {
+        let offset = usize::from(addr.checked_sub(self.origin)?);
+        let bytes = self.rom.get(offset..offset + 2)?;
+
+        Some(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+    }
+
+    fn decode(&self, addr: u16) -> Option<Instruction> {
+        let opcode = self.word(addr)?;
+        let next = if opcode == LONG_LOAD {
+            self.word(addr.checked_add(2)?)?
+        } else {
+            0
+        };
+
+        Instruction::decode(opcode, next).ok()
+    }
+
+    // follow every path from `entry`, recording the instructions and jump targets
+    fn trace(&mut self, entry: u16) {
+        let mut pending = vec![entry];
+        let mut calls = BTreeSet::new();
+        let mut jumps = BTreeSet::new();
+
+        while let Some(addr) = pending.pop() {
+            if self.code.contains_key(&addr) {
+                continue;
+            }
+            let instruction = match self.decode(addr) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            self.code.insert(addr, instruction);
+
+            let next = addr.wrapping_add(instruction.size());
+            match instruction {
+                Instruction(Opcode::RET, _) | Instruction(Opcode::EXIT, _) => {}
+                Instruction(Opcode::JP, AddressingMode::Addr(target)) => {
+                    jumps.insert(target);
+                    pending.push(target);
+                }
+                Instruction(Opcode::JP, AddressingMode::V0Addr(target)) => {
+                    jumps.insert(target);
+                }
+                Instruction(Opcode::CALL, AddressingMode::Addr(target)) => {
+                    calls.insert(target);
+                    pending.push(target);
+                    pending.push(next);
+                }
+                Instruction(Opcode::SE, _)
+                | Instruction(Opcode::SNE, _)
+                | Instruction(Opcode::SKP, _)
+                | Instruction(Opcode::SKNP, _) => {
+                    // the skipped instruction may be a 4 bytes long load
+                    let skipped = match self.word(next) {
+                        Some(LONG_LOAD) => 4,
+                        _ => 2,
+                    };
+                    pending.push(next);
+                    pending.push(next.wrapping_add(skipped));
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        // subroutines have a distinct prefix, even when they are also jumped to
+        let labels = jumps
+            .into_iter()
+            .map(|target| (target, format!("loc_{:03X}", target)))
+            .chain(
+                calls
+                    .into_iter()
+                    .map(|target| (target, format!("sub_{:03X}", target))),
+            )
+            .filter(|&(target, _)| self.contains(target))
+            .collect();
+        self.labels = labels;
+    }
+
+    /// Whether an instruction reachable from the entry point starts at `addr`
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.code.contains_key(&addr)
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    /// The ROM as a sequence of labels, instructions and data
+    pub fn lines(&self) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut data: Option<(u16, Vec<u8>)> = None;
+
+        let flush = |data: &mut Option<(u16, Vec<u8>)>, lines: &mut Vec<Line>| {
+            if let Some((addr, bytes)) = data.take() {
+                lines.push(Line::Data { addr, bytes });
+            }
+        };
+
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let addr = self.origin + offset as u16;
+
+            if let Some(label) = self.labels.get(&addr) {
+                flush(&mut data, &mut lines);
+                lines.push(Line::Label(label.clone()));
+            }
+
+            // an instruction overlapping another one (or a label) can't be listed as
+            // is, its bytes are kept as data instead
+            let instruction = self.code.get(&addr).filter(|instruction| {
+                (1..instruction.size()).all(|n| {
+                    let inner = addr + n;
+                    !self.code.contains_key(&inner) && !self.labels.contains_key(&inner)
+                })
+            });
+
+            match instruction {
+                Some(&instruction) => {
+                    flush(&mut data, &mut lines);
+
+                    let size = usize::from(instruction.size());
+                    lines.push(Line::Code {
+                        addr,
+                        bytes: self.rom[offset..offset + size].to_vec(),
+                        instruction,
+                    });
+                    offset += size;
+                }
+                None => {
+                    let (_, bytes) = data.get_or_insert_with(|| (addr, Vec::new()));
+                    bytes.push(self.rom[offset]);
+                    if bytes.len() == DATA_PER_LINE {
+                        flush(&mut data, &mut lines);
+                    }
+                    offset += 1;
+                }
+            }
+        }
+        flush(&mut data, &mut lines);
+
+        lines
+    }
+
+    // the instruction with its jump target replaced by a label, when there is one
+    fn mnemonic(&self, instruction: Instruction) -> String {
+        match instruction {
+            Instruction(opcode, AddressingMode::Addr(addr)) if opcode != Opcode::SYS => {
+                match self.label(addr) {
+                    Some(label) => format!("{} {}", opcode, label),
+                    None => instruction.to_string(),
+                }
+            }
+            Instruction(opcode, AddressingMode::V0Addr(addr)) => match self.label(addr) {
+                Some(label) => format!("{} V0, {}", opcode, label),
+                None => instruction.to_string(),
+            },
+            _ => instruction.to_string(),
+        }
+    }
+
+    /// A listing with the address and raw bytes of every line
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+
+        for line in self.lines() {
+            match line {
+                Line::Label(label) => writeln!(out, "{}:", label),
+                Line::Code {
+                    addr,
+                    bytes,
+                    instruction,
+                } => writeln!(
+                    out,
+                    "{:03X}  {:<23} {}",
+                    addr,
+                    hex_bytes(&bytes),
+                    self.mnemonic(instruction)
+                ),
+                Line::Data { addr, bytes } => writeln!(
+                    out,
+                    "{:03X}  {:<23} {}",
+                    addr,
+                    hex_bytes(&bytes),
+                    data_directive(&bytes)
+                ),
+            }
+            .unwrap();
+        }
+
+        out
+    }
+
+    /// Source code that assembles back into the same ROM
+    pub fn source(&self) -> String {
+        let mut out = String::new();
+
+        for line in self.lines() {
+            match line {
+                Line::Label(label) => writeln!(out, "{}:", label),
+                Line::Code { instruction, .. } => {
+                    writeln!(out, "    {}", self.mnemonic(instruction))
+                }
+                Line::Data { bytes, .. } => writeln!(out, "    {}", data_directive(&bytes)),
+            }
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn data_directive(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("#{:02X}", byte)).collect();
+    format!("db {}", bytes.join(", "))
+}
+
+/// Disassemble a ROM loaded at the usual `0x200`
+pub fn disassemble(rom: &[u8]) -> Disassembly {
+    Disassembly::new(rom, PROGRAM_START)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn uses_cowgod_mnemonics() {
+        let mnemonic = |opcode| Instruction::try_from(opcode).unwrap().to_string();
+
+        assert_eq!(mnemonic(0x632A), "LD V3, #2A");
+        assert_eq!(mnemonic(0xD015), "DRW V0, V1, 5");
+        assert_eq!(mnemonic(0x00E0), "CLS");
+        assert_eq!(mnemonic(0xB208), "JP V0, #208");
+        assert_eq!(mnemonic(0xF565), "LD V5, [I]");
+        assert_eq!(mnemonic(0x5132), "LD [I], V1-V3");
+        assert_eq!(
+            Instruction::decode(LONG_LOAD, 0x1234).unwrap().to_string(),
+            "LD I, LONG #1234"
+        );
+    }
+
+    #[test]
+    fn separates_code_from_sprites() {
+        let rom = [
+            0x22, 0x08, // 200: CALL sub_208
+            0xA2, 0x0C, // 202: LD I, #20C
+            0xD0, 0x11, // 204: DRW V0, V1, 1
+            0x12, 0x06, // 206: loc_206: JP loc_206
+            0x30, 0x01, // 208: sub_208: SE V0, #01
+            0x00, 0xEE, // 20A: RET
+            0xFF, 0x81, // 20C: sprite
+        ];
+        let disassembly = disassemble(&rom);
+
+        assert!(disassembly.is_code(0x20A));
+        assert!(!disassembly.is_code(0x20C));
+        assert_eq!(
+            disassembly.source(),
+            "    CALL sub_208\n    LD I, #20C\n    DRW V0, V1, 1\nloc_206:\n    JP loc_206\n\
+             sub_208:\n    SE V0, #01\n    RET\n    db #FF, #81\n"
+        );
+    }
+
+    #[test]
+    fn skips_over_long_loads() {
+        let rom = [
+            0x30, 0x00, // 200: SE V0, #00
+            0xF0, 0x00, 0x12, 0x34, // 202: LD I, LONG #1234
+            0x12, 0x06, // 206: JP loc_206
+        ];
+        let lines = disassemble(&rom).lines();
+
+        assert_eq!(
+            lines[1],
+            Line::Code {
+                addr: 0x202,
+                bytes: vec![0xF0, 0x00, 0x12, 0x34],
+                instruction: Instruction(Opcode::LD, AddressingMode::ILongAddr(0x1234)),
+            }
+        );
+        assert_eq!(lines[2], Line::Label("loc_206".to_string()));
+    }
+}
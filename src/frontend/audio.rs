@@ -0,0 +1,57 @@
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+
+use crate::audio::{AudioConfig, AudioSink, Buzzer};
+
+const SAMPLE_RATE: i32 = 44100;
+
+struct BuzzerCallback {
+    buzzer: Buzzer,
+    sample_rate: u32,
+}
+
+impl AudioCallback for BuzzerCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.buzzer.fill(out, self.sample_rate);
+    }
+}
+
+/// Plays the buzzer through the SDL2 audio device
+pub struct SdlAudio {
+    device: AudioDevice<BuzzerCallback>,
+}
+
+impl SdlAudio {
+    pub fn new(audio: &AudioSubsystem, config: AudioConfig) -> Result<Self, String> {
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio.open_playback(None, &spec, |spec| BuzzerCallback {
+            buzzer: Buzzer::new(config),
+            sample_rate: spec.freq as u32,
+        })?;
+        // the callback outputs silence while the buzzer is off
+        device.resume();
+
+        Ok(Self { device })
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn set_beeping(&mut self, beeping: bool) {
+        self.device.lock().buzzer.set_beeping(beeping);
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.device.lock().buzzer.set_pattern(pattern, pitch);
+    }
+
+    fn clear_pattern(&mut self) {
+        self.device.lock().buzzer.clear_pattern();
+    }
+}
@@ -1,30 +1,54 @@
-use std::{ops::Deref, usize};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::{fs, thread, time};
 
-use crate::cpu::{Status as CpuStatus, CPU};
+use crate::audio::AudioConfig;
+use crate::database::RomInfo;
+use crate::machine::Machine;
+
+mod audio;
+
+pub use audio::SdlAudio;
 
-use sdl2::{event::Event, keyboard::Keycode};
 use sdl2::{
-    pixels::Color,
-    rect::{Point, Rect},
+    event::Event,
+    keyboard::{Keycode, Mod},
 };
+use sdl2::{pixels::Color, rect::Point};
 use sdl2::{render::Canvas, video::Window};
 
-const WINDOW_TITLE: &'static str = "Protoshark's CHIP-8";
+const WINDOW_TITLE: &str = "Protoshark's CHIP-8";
 
 const SCREEN_WIDTH: usize = 64;
 const SCREEN_HEIGHT: usize = 32;
 const SCREEN_SCALE: usize = 8;
 
+// plays the game backwards while held, when the machine keeps a rewind history
+const REWIND_KEY: Keycode = Keycode::Backspace;
+
+// save state slots, bound to F1..F8
+const STATE_SLOTS: usize = 8;
+
+// how long to sleep between polls when no frame is due
+const IDLE_SLEEP: time::Duration = time::Duration::from_millis(1);
+
 const WINDOW_WIDTH: usize = SCREEN_WIDTH * SCREEN_SCALE;
 const WINDOW_HEIGHT: usize = SCREEN_HEIGHT * SCREEN_SCALE;
 
-const BG_COLOR: Color = Color::RGB(0x0F, 0x17, 0x13);
-const FG_COLOR: Color = Color::RGB(0x00, 0xFA, 0x00);
+/// Colours for each combination of lit bit planes: none, first, second and both
+pub type Palette = [Color; 4];
+
+const DEFAULT_PALETTE: Palette = [
+    Color::RGB(0x0F, 0x17, 0x13),
+    Color::RGB(0x00, 0xFA, 0x00),
+    Color::RGB(0xFA, 0x8C, 0x00),
+    Color::RGB(0xFA, 0xFA, 0xFA),
+];
 
-struct KeypadKey(usize);
+struct KeypadKey(u8);
 
 impl Deref for KeypadKey {
-    type Target = usize;
+    type Target = u8;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -54,14 +78,48 @@ impl KeypadKey {
     }
 }
 
-pub struct SDL<'c> {
+// keys bound to the game actions of the rom database, on top of the keypad layout
+const ACTION_KEYS: [(&str, Keycode); 6] = [
+    ("up", Keycode::Up),
+    ("down", Keycode::Down),
+    ("left", Keycode::Left),
+    ("right", Keycode::Right),
+    ("a", Keycode::Space),
+    ("b", Keycode::Return),
+];
+
+// the save state slot bound to a function key
+fn state_slot(keycode: Keycode) -> Option<usize> {
+    let keys = [
+        Keycode::F1,
+        Keycode::F2,
+        Keycode::F3,
+        Keycode::F4,
+        Keycode::F5,
+        Keycode::F6,
+        Keycode::F7,
+        Keycode::F8,
+    ];
+
+    keys.iter().position(|&key| key == keycode)
+}
+
+pub struct SDL<'m> {
     context: sdl2::Sdl,
+    audio: sdl2::AudioSubsystem,
     canvas: Canvas<Window>,
-    cpu: &'c mut CPU,
+    palette: Palette,
+    // extra keys bound to the keypad
+    bindings: Vec<(Keycode, u8)>,
+    machine: &'m mut Machine,
+
+    // save states, also written to `<state_path>.<slot>.state` when set
+    slots: [Option<Vec<u8>>; STATE_SLOTS],
+    state_path: Option<PathBuf>,
 }
 
-impl<'c> SDL<'c> {
-    pub fn new(cpu: &'c mut CPU) -> Self {
+impl<'m> SDL<'m> {
+    pub fn new(machine: &'m mut Machine) -> Self {
         let context = sdl2::init().expect("Could not initialize the sdl2 context");
 
         let video = context.video().expect("Could not load the video backend");
@@ -73,6 +131,12 @@ impl<'c> SDL<'c> {
             .build()
             .expect("Could not create a new window");
 
+        let audio = context.audio().expect("Could not load the audio backend");
+        match SdlAudio::new(&audio, AudioConfig::default()) {
+            Ok(sink) => machine.cpu.set_audio_sink(Box::new(sink)),
+            Err(error) => eprintln!("Could not open the audio device: {}", error),
+        }
+
         let canvas = window
             .into_canvas()
             .accelerated()
@@ -81,25 +145,98 @@ impl<'c> SDL<'c> {
 
         Self {
             context,
+            audio,
             canvas,
-            cpu,
+            palette: DEFAULT_PALETTE,
+            bindings: Vec::new(),
+            machine,
+
+            slots: Default::default(),
+            state_path: None,
         }
     }
 
-    pub fn run(&mut self) {
-        // setup the canvas
-        self.canvas
-            .set_scale(SCREEN_SCALE as f32, SCREEN_SCALE as f32)
-            .unwrap();
-        self.canvas.set_viewport(Rect::new(
-            // center x axis
-            1 / 4 * (WINDOW_WIDTH / SCREEN_SCALE - SCREEN_WIDTH) as i32,
-            // center y axis
-            1 / 4 * (WINDOW_HEIGHT / SCREEN_SCALE - SCREEN_HEIGHT) as i32,
-            (SCREEN_WIDTH * SCREEN_SCALE) as u32,
-            (SCREEN_HEIGHT * SCREEN_SCALE) as u32,
-        ));
+    // change the tone, waveform or volume of the buzzer
+    pub fn with_audio(self, config: AudioConfig) -> Self {
+        match SdlAudio::new(&self.audio, config) {
+            Ok(sink) => self.machine.cpu.set_audio_sink(Box::new(sink)),
+            Err(error) => eprintln!("Could not open the audio device: {}", error),
+        }
+        self
+    }
+
+    pub fn with_palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    // use the title, colours and controls the rom database has for the game
+    pub fn with_rom_info(mut self, info: &RomInfo) -> Self {
+        if let Err(error) = self.canvas.window_mut().set_title(&info.title) {
+            eprintln!("Could not set the window title: {}", error);
+        }
+
+        for (color, &[r, g, b]) in self.palette.iter_mut().zip(&info.colors) {
+            *color = Color::RGB(r, g, b);
+        }
 
+        self.bindings = ACTION_KEYS
+            .iter()
+            .filter_map(|&(action, keycode)| Some((keycode, info.key(action)?)))
+            .collect();
+        self
+    }
+
+    // the keypad key `keycode` is bound to
+    fn keypad_key(&self, keycode: Keycode) -> Option<u8> {
+        self.bindings
+            .iter()
+            .find(|&&(bound, _)| bound == keycode)
+            .map(|&(_, key)| key)
+            .or_else(|| KeypadKey::from_keycode(keycode).map(|key| *key))
+    }
+
+    // keep the save states on disk, next to `path`
+    pub fn with_state_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.state_path = Some(path.into());
+        self
+    }
+
+    fn slot_path(&self, slot: usize) -> Option<PathBuf> {
+        self.state_path.as_ref().map(|path| {
+            let mut path = path.clone().into_os_string();
+            path.push(format!(".{}.state", slot + 1));
+            PathBuf::from(path)
+        })
+    }
+
+    fn save_slot(&mut self, slot: usize) {
+        let state = self.machine.cpu.save_state();
+
+        if let Some(path) = self.slot_path(slot) {
+            if let Err(error) = fs::write(&path, &state) {
+                eprintln!("Could not write {}: {}", path.display(), error);
+            }
+        }
+        self.slots[slot] = Some(state);
+    }
+
+    fn load_slot(&mut self, slot: usize) {
+        if self.slots[slot].is_none() {
+            self.slots[slot] = self.slot_path(slot).and_then(|path| fs::read(path).ok());
+        }
+
+        match &self.slots[slot] {
+            Some(state) => {
+                if let Err(error) = self.machine.load_state(state) {
+                    eprintln!("Could not load the state in slot {}: {}", slot + 1, error);
+                }
+            }
+            None => eprintln!("Nothing saved in slot {}", slot + 1),
+        }
+    }
+
+    pub fn run(&mut self) {
         self.canvas.clear();
         self.canvas.present();
 
@@ -108,70 +245,108 @@ impl<'c> SDL<'c> {
             .event_pump()
             .expect("Could not get the event pump");
 
-        use std::time;
-
         let mut timer = time::Instant::now();
+        let mut rewinding = false;
 
         'running: loop {
-            if timer.elapsed() >= time::Duration::from_millis(1000 / 60) {
-                // run cpu cycle
-                for _ in 0..20 {
-                    self.cpu.cycle();
-                }
-
-                self.cpu.keypad = 0;
-
-                self.cpu.delay = self.cpu.delay.saturating_sub(1);
-                self.cpu.sound = self.cpu.sound.saturating_sub(1);
-
-
-                // grab input events
-                for event in event_pump.poll_iter() {
-                    match event {
-                        Event::Quit { .. }
-                        | Event::KeyDown {
-                            keycode: Some(Keycode::Escape),
-                            ..
-                        } => break 'running,
-                        Event::KeyDown {
-                            keycode: Some(keycode),
-                            ..
-                        } => {
-                            let key = KeypadKey::from_keycode(keycode);
-                            if let Some(key) = key {
-                                let index: usize = *key.deref();
-                                if let CpuStatus::WaitingKeypress(x) = self.cpu.status {
-                                    self.cpu.v[x] = index as u8;
-                                    self.cpu.status = CpuStatus::Running;
-                                }
-                                self.cpu.keypad = 1 << index
-                            }
+            // grab input events
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(REWIND_KEY),
+                        ..
+                    } => rewinding = true,
+                    Event::KeyUp {
+                        keycode: Some(REWIND_KEY),
+                        ..
+                    } => rewinding = false,
+                    // F1..F8 load a state, shift+F1..F8 save it
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        keymod,
+                        repeat: false,
+                        ..
+                    } if state_slot(keycode).is_some() => {
+                        let slot = state_slot(keycode).unwrap();
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            self.save_slot(slot);
+                        } else {
+                            self.load_slot(slot);
+                            self.update_screen();
+                        }
+                    }
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        repeat: false,
+                        ..
+                    } => {
+                        if let Some(key) = self.keypad_key(keycode) {
+                            self.machine.cpu.keypad.press(key);
                         }
-                        _ => {}
                     }
+                    Event::KeyUp {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        if let Some(key) = self.keypad_key(keycode) {
+                            self.machine.cpu.keypad.release(key);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            let elapsed = timer.elapsed();
+            timer = time::Instant::now();
+
+            if rewinding {
+                match self.machine.rewind_for(elapsed) {
+                    0 => thread::sleep(IDLE_SLEEP),
+                    _ => self.update_screen(),
                 }
-                self.update_screen();
+                continue;
+            }
 
-                timer = time::Instant::now()
+            match self.machine.step_for(elapsed) {
+                Ok(0) => thread::sleep(IDLE_SLEEP),
+                Ok(_) => self.update_screen(),
+                Err(error) => {
+                    // the cpu stays faulted, keep the last frame on screen
+                    eprintln!("CPU fault: {}", error);
+                    self.update_screen();
+                }
             }
         }
     }
 
     fn update_screen(&mut self) {
+        let bus = &self.machine.cpu.bus;
+        let (width, height) = (bus.width(), bus.height());
+
+        // high resolution mode fits twice the pixels in the same window
+        let scale = (WINDOW_WIDTH / width) as f32;
+        self.canvas.set_scale(scale, scale).unwrap();
+
+        self.canvas.set_draw_color(self.palette[0]);
         self.canvas.clear();
 
-        self.canvas.set_draw_color(FG_COLOR);
-        for i in 0..(64 * 32) {
-            let x = i % 64;
-            let y = i / 64;
+        for i in 0..(width * height) {
+            let x = i % width;
+            let y = i / width;
 
-            if self.cpu.bus.borrow().vram[i] == 1 {
+            let pixel = usize::from(bus.vram[i] & 0b11);
+            if pixel != 0 {
+                self.canvas.set_draw_color(self.palette[pixel]);
                 self.canvas
                     .draw_point(Point::new(x as i32, y as i32))
                     .unwrap()
             }
         }
-        self.canvas.set_draw_color(BG_COLOR);
         self.canvas.present();
     }
 }
@@ -1,7 +1,20 @@
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod assembler;
+pub mod audio;
+pub mod bus;
 pub mod cpu;
+pub mod database;
+pub mod debugger;
+pub mod disassembler;
+pub mod machine;
 pub mod mmu;
+pub mod rewind;
+pub mod state;
+pub mod trace;
 
 pub use cpu::CPU;
+pub use machine::Machine;
 pub use mmu::MMU;
 
 #[cfg(feature = "sdl")]
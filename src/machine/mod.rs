@@ -0,0 +1,352 @@
+use std::time::Duration;
+
+use crate::cpu::{CpuError, Status, CPU, VIP_INTERPRETER_CYCLES};
+use crate::rewind::Rewind;
+use crate::state::StateError;
+
+/// The delay and sound timers (and the vertical blank) run at 60Hz
+pub const TIMER_FREQUENCY: u32 = 60;
+
+/// 20 instructions per frame, a speed most CHIP-8 games are comfortable with
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = 1200;
+
+// the most frames `step_for` catches up on, so a long stall (the window being dragged,
+// a debugger pause) doesn't get emulated all at once
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+/// How the time of a frame is shared between instructions
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Timing {
+    /// Every instruction takes the same time, running the configured number of
+    /// instructions per second
+    #[default]
+    Fixed,
+    /// Every instruction takes as many machine cycles as on the COSMAC VIP, and
+    /// sprites wait for the vertical blank to be drawn (with the display wait quirk)
+    CosmacVip,
+}
+
+/// Drives a [`CPU`] in real time: runs instructions at a configurable speed and
+/// ticks the timers at 60Hz, so frontends only need to handle input and output.
+pub struct Machine {
+    pub cpu: CPU,
+
+    instructions_per_second: u32,
+    timing: Timing,
+
+    // frames emulated so far
+    frames: u64,
+    // instructions (or machine cycles, with the VIP timing) run in the current frame
+    frame_cycles: u64,
+    // machine cycles of a sprite drawn once the vertical blank is over
+    deferred_cycles: u64,
+    // time given to `step_for` that didn't fill a whole frame yet
+    pending: Duration,
+
+    // a snapshot taken at the end of every frame, when enabled
+    rewind: Option<Rewind>,
+}
+
+impl Machine {
+    pub fn new(cpu: CPU) -> Self {
+        Self {
+            cpu,
+            instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            timing: Timing::default(),
+            frames: 0,
+            frame_cycles: 0,
+            deferred_cycles: 0,
+            pending: Duration::from_secs(0),
+            rewind: None,
+        }
+    }
+
+    pub fn with_instructions_per_second(mut self, instructions_per_second: u32) -> Self {
+        self.set_instructions_per_second(instructions_per_second);
+        self
+    }
+
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second;
+    }
+
+    pub fn with_timing(mut self, timing: Timing) -> Self {
+        self.set_timing(timing);
+        self
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.frame_cycles = 0;
+        self.deferred_cycles = 0;
+    }
+
+    /// Keep the last `frames` frames around to go back to them with [`Machine::rewind`]
+    pub fn with_rewind(mut self, frames: usize) -> Self {
+        self.set_rewind(Some(frames));
+        self
+    }
+
+    pub fn set_rewind(&mut self, frames: Option<usize>) {
+        self.rewind = frames.map(|frames| {
+            let mut rewind = Rewind::new(frames);
+            rewind.push(self.cpu.snapshot());
+            rewind
+        });
+    }
+
+    /// Number of frames [`Machine::rewind`] can go back
+    pub fn rewind_frames(&self) -> usize {
+        self.rewind
+            .as_ref()
+            .map_or(0, |rewind| rewind.len().saturating_sub(1))
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // duration of a single frame
+    pub fn frame_duration() -> Duration {
+        Duration::from_secs(1) / TIMER_FREQUENCY
+    }
+
+    // instructions to run in the current frame, spreading the remainder of
+    // speeds that aren't a multiple of 60 across frames
+    fn instructions_this_frame(&self) -> u64 {
+        let ips = u64::from(self.instructions_per_second);
+        let frequency = u64::from(TIMER_FREQUENCY);
+        let frame = self.frames % frequency;
+
+        ips * (frame + 1) / frequency - ips * frame / frequency
+    }
+
+    // instructions, or machine cycles with the VIP timing, in the current frame
+    fn frame_budget(&self) -> u64 {
+        match self.timing {
+            Timing::Fixed => self.instructions_this_frame(),
+            Timing::CosmacVip => u64::from(VIP_INTERPRETER_CYCLES),
+        }
+    }
+
+    // run a single instruction, accounting for the time it took
+    fn advance(&mut self) -> Result<(), CpuError> {
+        if self.timing == Timing::Fixed {
+            self.frame_cycles += 1;
+            return self.cpu.cycle();
+        }
+
+        let cycles = match self.cpu.status() {
+            Status::Running => self
+                .cpu
+                .peek(self.cpu.pc)
+                .map_or(0, |instruction| self.cpu.vip_cycles(instruction)),
+            _ => 0,
+        };
+        let result = self.cpu.cycle();
+
+        match self.cpu.status() {
+            Status::Running => self.frame_cycles += u64::from(cycles),
+            status => {
+                // the sprite is drawn right after the vertical blank, eating into
+                // the next frame
+                if status == Status::WaitingVblank {
+                    self.deferred_cycles = u64::from(cycles);
+                }
+                // waiting (for the vertical blank or a key) or stopped, the rest
+                // of the frame goes by idle
+                self.frame_cycles = self.frame_cycles.max(self.frame_budget());
+            }
+        }
+
+        result
+    }
+
+    // signal the vertical blank and tick the timers
+    fn end_frame(&mut self) {
+        self.cpu.vblank();
+        self.cpu.tick_timers();
+
+        // an instruction running past the end of the frame takes from the next one
+        let overrun = match self.timing {
+            Timing::Fixed => 0,
+            Timing::CosmacVip => self.frame_cycles.saturating_sub(self.frame_budget()),
+        };
+        self.frames += 1;
+        self.frame_cycles = overrun + self.deferred_cycles;
+        self.deferred_cycles = 0;
+
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.push(self.cpu.snapshot());
+        }
+    }
+
+    /// Run a single instruction, ending the frame once it ran all its instructions
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        let result = self.advance();
+
+        if self.frame_cycles >= self.frame_budget() {
+            self.end_frame();
+        }
+
+        result
+    }
+
+    /// Emulate the rest of the current frame: the instructions for 1/60s followed by
+    /// the vertical blank and a timer tick.
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        while self.frame_cycles < self.frame_budget() {
+            self.advance()?;
+        }
+
+        self.end_frame();
+        Ok(())
+    }
+
+    /// Go back `frames` frames, to the end of an earlier frame (the progress made in the
+    /// current one is dropped). Returns how many frames were rewound, which is less
+    /// than asked for when the history doesn't go back that far.
+    ///
+    /// The keypad is live input, so it keeps its current state.
+    pub fn rewind(&mut self, frames: usize) -> usize {
+        let rewind = match self.rewind.as_mut() {
+            Some(rewind) => rewind,
+            None => return 0,
+        };
+
+        let rewound = rewind.rewind(frames);
+        if rewound > 0 || self.frame_cycles > 0 {
+            let keypad = self.cpu.keypad;
+            let snapshot = rewind.latest().expect("rewind history is never empty");
+            self.cpu
+                .load_state(snapshot)
+                .expect("invalid rewind snapshot");
+            self.cpu.keypad = keypad;
+
+            self.frames -= rewound as u64;
+            self.frame_cycles = 0;
+            self.deferred_cycles = 0;
+        }
+
+        rewound
+    }
+
+    /// Rewind as many frames as fit in `elapsed` of real time, playing the game
+    /// backwards at its normal speed. Returns how many frames were rewound.
+    pub fn rewind_for(&mut self, elapsed: Duration) -> usize {
+        self.pending += elapsed;
+
+        let mut frames = 0;
+        while self.pending >= Self::frame_duration() {
+            self.pending -= Self::frame_duration();
+            frames += 1;
+        }
+
+        self.rewind(frames)
+    }
+
+    /// Restore a state made by [`CPU::save_state`]. The rewind history starts over
+    /// from it, so rewinding can't go back to before the load.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        self.cpu.load_state(state)?;
+        self.frame_cycles = 0;
+        self.deferred_cycles = 0;
+
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.clear();
+            rewind.push(self.cpu.snapshot());
+        }
+        Ok(())
+    }
+
+    /// Emulate `elapsed` of real time, running every whole frame that fits in it.
+    /// The leftover is kept for the next call. Returns how many frames were run.
+    ///
+    /// After a stall, only the last few frames are caught up on.
+    pub fn step_for(&mut self, elapsed: Duration) -> Result<u32, CpuError> {
+        let most = Self::frame_duration() * MAX_CATCH_UP_FRAMES;
+        self.pending = (self.pending + elapsed).min(most);
+
+        let mut frames = 0;
+        while self.pending >= Self::frame_duration() {
+            self.pending -= Self::frame_duration();
+            frames += 1;
+
+            self.run_frame()?;
+        }
+
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Quirks;
+    use crate::mmu::MMU;
+
+    fn machine(program: &[u8], timing: Timing) -> Machine {
+        let mut mmu = MMU::default();
+        for (i, &byte) in program.iter().enumerate() {
+            mmu.wb(0x200 + i, byte).unwrap();
+        }
+        mmu.lock_rom();
+
+        Machine::new(CPU::new(mmu, Quirks::cosmac_vip())).with_timing(timing)
+    }
+
+    #[test]
+    fn vip_timing_charges_machine_cycles() {
+        // ADD V0, V1; JP 200
+        let program = [0x80, 0x14, 0x12, 0x00];
+
+        let mut fixed = machine(&program, Timing::Fixed);
+        fixed.run_frame().unwrap();
+        assert_eq!(fixed.cpu.cycles(), 20);
+
+        // 84 and 52 of the 2598 machine cycles of a frame, the last instruction
+        // running over into the next frame
+        let mut vip = machine(&program, Timing::CosmacVip);
+        vip.run_frame().unwrap();
+        assert_eq!(vip.cpu.cycles(), 39);
+        assert_eq!(vip.frame_cycles, 19 * 136 + 84 - 2598);
+        vip.run_frame().unwrap();
+        assert_eq!(vip.cpu.cycles(), 77);
+    }
+
+    #[test]
+    fn vip_timing_draws_after_the_vertical_blank() {
+        // DRW V0, V0, 15; JP 200
+        let program = [0xD0, 0x0F, 0x12, 0x00];
+        let mut vip = machine(&program, Timing::CosmacVip);
+
+        // the frame ends as soon as the sprite is drawn, and the next one starts
+        // with its 576 machine cycles
+        vip.run_frame().unwrap();
+        assert_eq!(vip.cpu.cycles(), 1);
+        assert_eq!(vip.frame_cycles, 576);
+
+        vip.run_frame().unwrap();
+        assert_eq!(vip.cpu.cycles(), 3);
+        assert_eq!(vip.frame_cycles, 576);
+    }
+
+    #[test]
+    fn long_stalls_are_not_caught_up_on() {
+        // JP 200
+        let mut machine = machine(&[0x12, 0x00], Timing::Fixed);
+
+        assert_eq!(machine.step_for(Machine::frame_duration() * 2), Ok(2));
+        assert_eq!(
+            machine.step_for(Duration::from_secs(5)),
+            Ok(MAX_CATCH_UP_FRAMES)
+        );
+        assert_eq!(machine.frames(), 2 + u64::from(MAX_CATCH_UP_FRAMES));
+    }
+}
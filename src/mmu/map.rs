@@ -0,0 +1,89 @@
+use std::{error, fmt};
+
+use super::{INTERPRETER_SIZE, MAX_MEMORY_SIZE};
+
+/// The size of the address space and where programs are loaded in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// Bytes of memory, the fonts included
+    pub size: usize,
+    /// Where programs are loaded and start running
+    pub load_address: usize,
+}
+
+impl MemoryMap {
+    /// The 4KiB of the COSMAC VIP, with programs at 0x200
+    pub const fn chip8() -> Self {
+        Self {
+            size: 0x1000,
+            load_address: 0x200,
+        }
+    }
+
+    /// The 4KiB of the ETI-660, whose programs start at 0x600
+    pub const fn eti660() -> Self {
+        Self {
+            size: 0x1000,
+            load_address: 0x600,
+        }
+    }
+
+    /// The whole 64KiB addressable by XO-CHIP
+    pub const fn xo_chip() -> Self {
+        Self {
+            size: 0x10000,
+            load_address: 0x200,
+        }
+    }
+
+    pub const fn with_load_address(mut self, load_address: usize) -> Self {
+        self.load_address = load_address;
+        self
+    }
+
+    // the most bytes a program can take
+    pub fn capacity(&self) -> usize {
+        self.size.saturating_sub(self.load_address)
+    }
+
+    /// Check the memory holds the fonts and fits 16 bits addresses, and that programs
+    /// load past the fonts
+    pub fn validate(&self) -> Result<(), MapError> {
+        if !(INTERPRETER_SIZE..=MAX_MEMORY_SIZE).contains(&self.size) {
+            return Err(MapError::InvalidSize(self.size));
+        }
+        if !(INTERPRETER_SIZE..self.size).contains(&self.load_address) {
+            return Err(MapError::InvalidLoadAddress(self.load_address));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self::chip8()
+    }
+}
+
+/// Errors raised for a memory map no memory can be created with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// The memory is smaller than the fonts or larger than 64KiB
+    InvalidSize(usize),
+    /// The load address is within the fonts or past the end of memory
+    InvalidLoadAddress(usize),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::InvalidSize(size) => write!(f, "invalid memory size: 0x{:x}", size),
+            MapError::InvalidLoadAddress(address) => {
+                write!(f, "games can't be loaded at 0x{:04x}", address)
+            }
+        }
+    }
+}
+
+impl error::Error for MapError {}
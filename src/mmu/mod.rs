@@ -1,10 +1,30 @@
+use std::cell::RefCell;
+use std::io::{self, Read};
 use std::path::Path;
-use std::{fs, io};
+use std::{error, fmt, fs};
 
-const ROM_SIZE: usize = 0x200;
-const UPPER_ROM_SIZE: usize = 0x400;
-const RAM_SIZE: usize = 0xA00;
-const VRAM_SIZE: usize = 64 * 32;
+use crate::state::{Chunks, Put, StateError, Writer};
+
+mod map;
+mod protection;
+
+pub use map::{MapError, MemoryMap};
+pub use protection::{Protection, Region, WritePolicy};
+
+// the fonts, and the interpreter on the original machines, live below it
+const INTERPRETER_SIZE: usize = 0x200;
+// the most a 16 bits address reaches
+const MAX_MEMORY_SIZE: usize = 0x10000;
+
+// the screen is 64x32 in low resolution and 128x64 in (SUPER-CHIP) high resolution
+const LORES_WIDTH: usize = 64;
+const LORES_HEIGHT: usize = 32;
+const HIRES_WIDTH: usize = 128;
+const HIRES_HEIGHT: usize = 64;
+const VRAM_SIZE: usize = HIRES_WIDTH * HIRES_HEIGHT;
+
+/// Where the large SUPER-CHIP font is loaded, right after the small one
+pub const BIG_FONT_OFFSET: usize = 0x50;
 
 const CH8_FONT: [u8; 0x50] = [
     0x60, 0x90, 0x90, 0x90, 0x60, // 0
@@ -25,92 +45,584 @@ const CH8_FONT: [u8; 0x50] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+const SCHIP_FONT: [u8; 0xA0] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// Errors raised by an invalid memory access
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryError {
+    OutOfRange(usize),
+    /// A write to a region whose policy is [`WritePolicy::Fault`]
+    Protected {
+        offset: usize,
+        region: Region,
+    },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryError::OutOfRange(offset) => {
+                write!(f, "attempt to access an invalid offset: 0x{:04x}", offset)
+            }
+            MemoryError::Protected { offset, region } => {
+                write!(
+                    f,
+                    "attempt to write to {} memory at 0x{:04x}",
+                    region, offset
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for MemoryError {}
+
+/// Errors raised while loading a game
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The game doesn't fit between the load address and the end of memory
+    TooLarge {
+        size: usize,
+        capacity: usize,
+    },
+    /// The archive holds this many roms instead of a single one
+    RomsInArchive(usize),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{}", error),
+            LoadError::TooLarge { size, capacity } => write!(
+                f,
+                "the game takes {} bytes but only {} fit in memory",
+                size, capacity
+            ),
+            LoadError::RomsInArchive(0) => write!(f, "the archive holds no rom"),
+            LoadError::RomsInArchive(roms) => {
+                write!(f, "the archive holds {} roms instead of one", roms)
+            }
+        }
+    }
+}
+
+impl error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LoadError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+#[cfg(feature = "zip")]
+impl From<zip::result::ZipError> for LoadError {
+    fn from(error: zip::result::ZipError) -> Self {
+        match error {
+            zip::result::ZipError::Io(error) => LoadError::Io(error),
+            error => LoadError::Io(io::Error::new(io::ErrorKind::InvalidData, error)),
+        }
+    }
+}
+
+/// Extensions of the roms looked for in archives
+#[cfg(feature = "zip")]
+const ROM_EXTENSIONS: [&str; 3] = ["ch8", "sc8", "xo8"];
+
+/// A data access to memory, as recorded by the access log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read(usize),
+    /// The offset and the byte written
+    Write(usize, u8),
+}
+
 pub struct MMU {
-    pub rom: [u8; ROM_SIZE],
-    pub upper_rom: [u8; UPPER_ROM_SIZE],
-    ram: [u8; RAM_SIZE],
+    // the whole address space, fonts at the start
+    memory: Box<[u8]>,
+    map: MemoryMap,
+    // bytes of the program loaded at the load address
+    program_len: usize,
 
+    // each pixel holds a bitmask of the (XO-CHIP) bit planes lit on it
     pub vram: Box<[u8; VRAM_SIZE]>,
+    hires: bool,
+    // bit planes affected by drawing, clearing and scrolling
+    planes: u8,
 
     locked_rom: bool,
+    // what writes to each region do once the rom is locked
+    protection: Protection,
+
+    // data accesses made since the last `take_accesses`, when enabled
+    access_log: Option<RefCell<Vec<Access>>>,
 }
 
 impl Default for MMU {
     fn default() -> Self {
-        let mut rom = [0u8; ROM_SIZE];
-        for (i, &byte) in CH8_FONT.iter().enumerate() {
-            rom[i] = byte
-        }
+        Self::new(MemoryMap::default()).expect("the default memory map is valid")
+    }
+}
 
-        Self {
-            rom,
-            upper_rom: [0; UPPER_ROM_SIZE],
-            ram: [0; RAM_SIZE],
+#[allow(dead_code)]
+impl MMU {
+    /// Create a mmu with the fonts loaded and the rest of `map` cleared
+    pub fn new(map: MemoryMap) -> Result<Self, MapError> {
+        map.validate()?;
+
+        let mut memory = vec![0; map.size].into_boxed_slice();
+        memory[..CH8_FONT.len()].copy_from_slice(&CH8_FONT);
+        memory[BIG_FONT_OFFSET..BIG_FONT_OFFSET + SCHIP_FONT.len()].copy_from_slice(&SCHIP_FONT);
+
+        Ok(Self {
+            memory,
+            map,
+            program_len: 0,
 
             vram: Box::new([0; VRAM_SIZE]),
+            hires: false,
+            planes: 0b01,
 
             locked_rom: false,
+            protection: Protection::default(),
+
+            access_log: None,
+        })
+    }
+
+    // create a mmu with the 64KiB XO-CHIP address space
+    pub fn xo_chip() -> Self {
+        Self::new(MemoryMap::xo_chip()).expect("the XO-CHIP memory map is valid")
+    }
+
+    pub fn map(&self) -> MemoryMap {
+        self.map
+    }
+    // where the program is loaded and starts running
+    pub fn load_address(&self) -> usize {
+        self.map.load_address
+    }
+
+    /// SHA-1 of the loaded program, in lowercase hexadecimal
+    pub fn program_sha1(&self) -> String {
+        let start = self.map.load_address;
+        let program = &self.memory[start..start + self.program_len];
+
+        sha1_smol::Sha1::from(program).digest().to_string()
+    }
+
+    // size of the whole address space
+    pub fn memory_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Record every data access (instruction fetches excluded) for debugging
+    pub fn log_accesses(&mut self, enabled: bool) {
+        match (enabled, &self.access_log) {
+            (true, None) => self.access_log = Some(RefCell::default()),
+            (false, _) => self.access_log = None,
+            _ => {}
         }
     }
-}
 
-#[allow(dead_code)]
-impl MMU {
-    pub fn rb(&self, offset: usize) -> u8 {
-        match offset {
-            0x000..=0x1FF => self.rom[offset],
-            0x200..=0x5FF => self.upper_rom[offset - 0x200],
-            0x600..=0xFFF => self.ram[offset - 0x600],
-            _ => panic!("Attempt to read an invalid offset: 0x{:04x}", offset),
+    /// Drain the accesses recorded so far
+    pub fn take_accesses(&self) -> Vec<Access> {
+        self.access_log
+            .as_ref()
+            .map(|log| log.take())
+            .unwrap_or_default()
+    }
+
+    fn log(&self, access: Access) {
+        if let Some(log) = &self.access_log {
+            log.borrow_mut().push(access);
+        }
+    }
+
+    pub fn rb(&self, offset: usize) -> Result<u8, MemoryError> {
+        let byte = self.peek(offset)?;
+        self.log(Access::Read(offset));
+
+        Ok(byte)
+    }
+
+    // read a byte without logging the access
+    pub fn peek(&self, offset: usize) -> Result<u8, MemoryError> {
+        self.memory
+            .get(offset)
+            .copied()
+            .ok_or(MemoryError::OutOfRange(offset))
+    }
+    // the region `offset` belongs to: the fonts are in the interpreter area and the
+    // program is the loaded game, the rest (even below a program loaded high) is ram
+    pub fn region(&self, offset: usize) -> Region {
+        let program = self.map.load_address..self.map.load_address + self.program_len;
+
+        if offset < INTERPRETER_SIZE {
+            Region::Font
+        } else if program.contains(&offset) {
+            Region::Program
+        } else {
+            Region::Ram
         }
     }
-    pub fn wb(&mut self, offset: usize, byte: u8) {
-        match offset {
-            0x200..=0x5FF if !self.locked_rom => self.upper_rom[offset - 0x200] = byte,
-            0x600..=0xFFF => self.ram[offset - 0x600] = byte,
-            0x200..=0x5FF => self.upper_rom[offset - 0x200] = byte,
-            0x000..=0x1FF => self.rom[offset] = byte,
-            _ => panic!("Invalid offset"),
+
+    pub fn protection(&self) -> Protection {
+        self.protection
+    }
+    pub fn set_protection(&mut self, protection: Protection) {
+        self.protection = protection;
+    }
+
+    pub fn wb(&mut self, offset: usize, byte: u8) -> Result<(), MemoryError> {
+        if offset >= self.memory.len() {
+            return Err(MemoryError::OutOfRange(offset));
+        }
+
+        if self.locked_rom {
+            let region = self.region(offset);
+            match self.protection.policy(region) {
+                WritePolicy::Allow => {}
+                WritePolicy::Ignore => {
+                    // the program still made the access
+                    self.log(Access::Write(offset, byte));
+                    return Ok(());
+                }
+                WritePolicy::Fault => return Err(MemoryError::Protected { offset, region }),
+            }
         }
+
+        self.memory[offset] = byte;
+        self.log(Access::Write(offset, byte));
+
+        Ok(())
     }
 
-    pub fn rw(&self, offset: usize) -> u16 {
-        u16::from(self.rb(offset)) << 8 | u16::from(self.rb(offset + 1))
+    pub fn rw(&self, offset: usize) -> Result<u16, MemoryError> {
+        Ok(u16::from(self.rb(offset)?) << 8 | u16::from(self.rb(offset + 1)?))
+    }
+    // read an instruction word, which isn't a data access
+    pub fn fetch(&self, offset: usize) -> Result<u16, MemoryError> {
+        Ok(u16::from(self.peek(offset)?) << 8 | u16::from(self.peek(offset + 1)?))
     }
-    pub fn ww(&mut self, offset: usize, word: u16) {
+    pub fn ww(&mut self, offset: usize, word: u16) -> Result<(), MemoryError> {
         let high = word >> 8;
         let low = word & 0xFF;
 
-        self.wb(offset, high as u8);
-        self.wb(offset + 1, low as u8);
+        self.wb(offset, high as u8)?;
+        self.wb(offset + 1, low as u8)
     }
 
+    // flip the planes in `byte` for the pixel at (x, y), returning whether a lit pixel was erased
     pub fn wb_vram(&mut self, x: usize, y: usize, byte: u8) -> bool {
-        let offset = x + y * 64;
+        let offset = x + y * self.width();
 
         let old_value = self.vram[offset];
         self.vram[offset] ^= byte;
 
-        byte == old_value
+        old_value & byte != 0
     }
     pub fn rb_vram(&self, x: usize, y: usize) -> u8 {
-        let offset = x + y * 64;
+        let offset = x + y * self.width();
 
         self.vram[offset]
     }
 
-    pub fn load_game<P: AsRef<Path>>(&mut self, game_path: P) -> io::Result<()> {
-        let game_content = fs::read(game_path)?;
+    /// Draw `sprite`, `width` pixels wide (8 or 16), at (x, y) on every selected plane,
+    /// returning whether any lit pixel was erased (the collision flag).
+    ///
+    /// With more than one plane selected the sprite holds the data for each plane in turn.
+    /// The starting position always wraps around the screen, while pixels crossing
+    /// its edges are either clipped or wrapped.
+    pub fn draw_sprite(
+        &mut self,
+        x: usize,
+        y: usize,
+        sprite: &[u8],
+        width: usize,
+        clip: bool,
+    ) -> bool {
+        let (screen_width, screen_height) = (self.width(), self.height());
+        let (x, y) = (x % screen_width, y % screen_height);
+
+        let planes = (0..2)
+            .map(|p| 1 << p)
+            .filter(|p| self.planes & p != 0)
+            .collect::<Vec<u8>>();
+        let row_bytes = width / 8;
+        if planes.is_empty() || row_bytes == 0 {
+            return false;
+        }
+
+        let plane_size = sprite.len() / planes.len();
+        let mut collision = false;
+
+        for (&plane, data) in planes.iter().zip(sprite.chunks(plane_size.max(1))) {
+            for (row, row_data) in data.chunks(row_bytes).enumerate() {
+                for bit in 0..width {
+                    let lit = matches!(row_data.get(bit / 8), Some(byte) if byte & (0x80 >> (bit % 8)) != 0);
+                    if !lit {
+                        continue;
+                    }
+
+                    let (px, py) = (x + bit, y + row);
+                    if clip && (px >= screen_width || py >= screen_height) {
+                        continue;
+                    }
+
+                    collision |= self.wb_vram(px % screen_width, py % screen_height, plane);
+                }
+            }
+        }
+
+        collision
+    }
+
+    // width of the screen in the current resolution
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            LORES_WIDTH
+        }
+    }
+    // height of the screen in the current resolution
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+    // switch between low and high resolution, clearing every plane
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        for b in self.vram.iter_mut() {
+            *b = 0;
+        }
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+    // select the bit planes affected by the display instructions (XO-CHIP)
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
+    }
+
+    // clear the selected planes
+    pub fn clear_vram(&mut self) {
+        for b in self.vram.iter_mut() {
+            *b &= !self.planes;
+        }
+    }
+
+    // move the selected planes of the pixel at `from` to `to`
+    fn move_pixel(&mut self, from: Option<usize>, to: usize) {
+        let planes = self.planes;
+        let moved = from.map_or(0, |from| self.vram[from] & planes);
+
+        self.vram[to] = (self.vram[to] & !planes) | moved;
+    }
+
+    // scroll the screen down by `n` pixels
+    pub fn scroll_down(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let from = if y >= n {
+                    Some(x + (y - n) * width)
+                } else {
+                    None
+                };
+                self.move_pixel(from, x + y * width);
+            }
+        }
+    }
+    // scroll the screen right by `n` pixels
+    pub fn scroll_right(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+
+        for y in 0..height {
+            for x in (0..width).rev() {
+                let from = if x >= n {
+                    Some(x - n + y * width)
+                } else {
+                    None
+                };
+                self.move_pixel(from, x + y * width);
+            }
+        }
+    }
+    // scroll the screen left by `n` pixels
+    pub fn scroll_left(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+
+        for y in 0..height {
+            for x in 0..width {
+                let from = if x + n < width {
+                    Some(x + n + y * width)
+                } else {
+                    None
+                };
+                self.move_pixel(from, x + y * width);
+            }
+        }
+    }
+
+    /// Load the game at `game_path`, which can be a zip archive holding a single rom
+    /// when built with the `zip` feature
+    pub fn load_game<P: AsRef<Path>>(&mut self, game_path: P) -> Result<(), LoadError> {
+        let game_path = game_path.as_ref();
+
+        #[cfg(feature = "zip")]
+        {
+            let is_zip = game_path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"));
+            if is_zip {
+                return self.load_rom_zip(fs::File::open(game_path)?);
+            }
+        }
+
+        self.load_rom_bytes(&fs::read(game_path)?)
+    }
+
+    /// Load the game read from `reader` until its end
+    pub fn load_rom_reader<R: Read>(&mut self, mut reader: R) -> Result<(), LoadError> {
+        let mut program = Vec::new();
+        reader.read_to_end(&mut program)?;
+
+        self.load_rom_bytes(&program)
+    }
+
+    /// Load the single rom (`.ch8`, `.sc8` or `.xo8`) of a zip archive
+    #[cfg(feature = "zip")]
+    pub fn load_rom_zip<R: Read + io::Seek>(&mut self, reader: R) -> Result<(), LoadError> {
+        let mut archive = zip::ZipArchive::new(reader)?;
+
+        let roms: Vec<String> = archive
+            .file_names()
+            .filter(|name| {
+                let extension = Path::new(name).extension();
+                ROM_EXTENSIONS.iter().any(|&rom| {
+                    extension.is_some_and(|extension| extension.eq_ignore_ascii_case(rom))
+                })
+            })
+            .map(String::from)
+            .collect();
+        if roms.len() != 1 {
+            return Err(LoadError::RomsInArchive(roms.len()));
+        }
+
+        let rom = archive.by_name(&roms[0])?;
+        self.load_rom_reader(rom)
+    }
 
-        for (i, &byte) in game_content.iter().enumerate() {
-            self.upper_rom[i] = byte
+    /// Copy `program` at the load address and lock the rom
+    pub fn load_rom_bytes(&mut self, program: &[u8]) -> Result<(), LoadError> {
+        let start = self.map.load_address;
+        if program.len() > self.map.capacity() {
+            return Err(LoadError::TooLarge {
+                size: program.len(),
+                capacity: self.map.capacity(),
+            });
         }
 
+        // clear what a longer program loaded before left behind
+        self.memory[start..start + self.program_len].fill(0);
+        self.memory[start..start + program.len()].copy_from_slice(program);
+        self.program_len = program.len();
+
         // lock the rom when the game is loaded
         self.lock_rom();
         Ok(())
     }
 
+    // write the memory and the screen as save state chunks
+    pub(crate) fn save_state(&self, writer: &mut Writer) {
+        let mut memory = Vec::new();
+        memory.put_u8(u8::from(self.locked_rom));
+
+        memory.put_rle(&self.memory, writer.compress);
+        memory.put_u16(self.map.load_address as u16);
+        memory.put_u16(self.program_len as u16);
+        writer.chunk(b"MEM ", &memory);
+
+        let mut screen = Vec::new();
+        screen.put_u8(u8::from(self.hires));
+        screen.put_u8(self.planes);
+        screen.put_rle(&self.vram[..], writer.compress);
+        writer.chunk(b"VRAM", &screen);
+    }
+
+    // restore a mmu from the chunks written by `save_state`
+    pub(crate) fn load_state(chunks: &Chunks) -> Result<Self, StateError> {
+        let mut memory = chunks.get(b"MEM ")?;
+        let locked_rom = memory.u8()? != 0;
+
+        let bytes = memory.rle()?;
+        if !(INTERPRETER_SIZE..=MAX_MEMORY_SIZE).contains(&bytes.len()) {
+            return Err(StateError::Invalid("memory size"));
+        }
+
+        let load_address = usize::from(memory.u16()?);
+        let program_len = usize::from(memory.u16()?);
+        if load_address < INTERPRETER_SIZE || load_address + program_len > bytes.len() {
+            return Err(StateError::Invalid("program area"));
+        }
+
+        let mut mmu = MMU::new(MemoryMap {
+            size: bytes.len(),
+            load_address,
+        })
+        .map_err(|_| StateError::Invalid("program area"))?;
+        mmu.memory.copy_from_slice(&bytes);
+        mmu.program_len = program_len;
+        mmu.locked_rom = locked_rom;
+
+        let mut screen = chunks.get(b"VRAM")?;
+        mmu.hires = screen.u8()? != 0;
+        mmu.planes = screen.u8()? & 0b11;
+
+        let vram = screen.rle()?;
+        if vram.len() != VRAM_SIZE {
+            return Err(StateError::Invalid("vram size"));
+        }
+        mmu.vram.copy_from_slice(&vram);
+
+        Ok(mmu)
+    }
+
     pub fn lock_rom(&mut self) {
         self.locked_rom = true;
     }
@@ -118,3 +630,314 @@ impl MMU {
         self.locked_rom
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        LoadError, MapError, MemoryError, MemoryMap, Protection, Region, WritePolicy, MMU,
+    };
+
+    const BLOCK: [u8; 2] = [0xF0, 0xF0];
+
+    fn lit_pixels(mmu: &MMU) -> usize {
+        mmu.vram.iter().filter(|&&pixel| pixel != 0).count()
+    }
+
+    #[test]
+    fn drawing_on_blank_screen_does_not_collide() {
+        let mut mmu = MMU::default();
+
+        assert!(!mmu.draw_sprite(0, 0, &BLOCK, 8, false));
+        assert_eq!(lit_pixels(&mmu), 8);
+    }
+
+    #[test]
+    fn drawing_same_sprite_twice_collides_and_erases() {
+        let mut mmu = MMU::default();
+
+        mmu.draw_sprite(10, 5, &BLOCK, 8, false);
+        assert!(mmu.draw_sprite(10, 5, &BLOCK, 8, false));
+        assert_eq!(lit_pixels(&mmu), 0);
+    }
+
+    #[test]
+    fn partial_overlap_collides() {
+        let mut mmu = MMU::default();
+
+        mmu.draw_sprite(0, 0, &BLOCK, 8, false);
+        assert!(mmu.draw_sprite(3, 1, &BLOCK, 8, false));
+        // 8 + 8 pixels drawn, 1 row of 1 pixel overlapping
+        assert_eq!(lit_pixels(&mmu), 14);
+        assert_eq!(mmu.rb_vram(3, 1), 0);
+    }
+
+    #[test]
+    fn adjacent_sprites_do_not_collide() {
+        let mut mmu = MMU::default();
+
+        mmu.draw_sprite(0, 0, &BLOCK, 8, false);
+        assert!(!mmu.draw_sprite(4, 0, &BLOCK, 8, false));
+        assert!(!mmu.draw_sprite(0, 2, &BLOCK, 8, false));
+        assert_eq!(lit_pixels(&mmu), 24);
+    }
+
+    #[test]
+    fn blank_sprite_rows_over_lit_pixels_do_not_collide() {
+        let mut mmu = MMU::default();
+
+        mmu.draw_sprite(0, 0, &BLOCK, 8, false);
+        assert!(!mmu.draw_sprite(0, 0, &[0x00, 0x00], 8, false));
+        assert!(!mmu.draw_sprite(0, 0, &[0x0F, 0x0F], 8, false));
+        assert_eq!(lit_pixels(&mmu), 16);
+    }
+
+    #[test]
+    fn drawing_over_erased_pixels_does_not_collide() {
+        let mut mmu = MMU::default();
+
+        mmu.draw_sprite(0, 0, &BLOCK, 8, false);
+        mmu.draw_sprite(0, 0, &BLOCK, 8, false);
+        assert!(!mmu.draw_sprite(0, 0, &BLOCK, 8, false));
+    }
+
+    #[test]
+    fn wrapped_pixels_collide() {
+        let mut mmu = MMU::default();
+
+        mmu.draw_sprite(0, 0, &[0x80], 8, false);
+        assert!(mmu.draw_sprite(63, 0, &[0xC0], 8, false));
+        assert_eq!(mmu.rb_vram(0, 0), 0);
+        assert_eq!(mmu.rb_vram(63, 0), 1);
+    }
+
+    #[test]
+    fn clipped_pixels_do_not_collide() {
+        let mut mmu = MMU::default();
+
+        mmu.draw_sprite(0, 0, &[0x80], 8, false);
+        assert!(!mmu.draw_sprite(63, 0, &[0xC0], 8, true));
+        assert_eq!(mmu.rb_vram(0, 0), 1);
+        assert_eq!(lit_pixels(&mmu), 2);
+    }
+
+    #[test]
+    fn starting_position_wraps_even_when_clipping() {
+        let mut mmu = MMU::default();
+
+        mmu.draw_sprite(64 + 2, 32 + 1, &[0x80], 8, true);
+        assert_eq!(mmu.rb_vram(2, 1), 1);
+    }
+
+    #[test]
+    fn wide_sprites_collide_on_their_right_half() {
+        let mut mmu = MMU::default();
+        mmu.set_hires(true);
+
+        let sprite = [0xFF; 32];
+        assert!(!mmu.draw_sprite(0, 0, &sprite, 16, false));
+        assert_eq!(lit_pixels(&mmu), 256);
+
+        assert!(mmu.draw_sprite(15, 15, &[0x80, 0x00], 8, false));
+        assert!(!mmu.draw_sprite(16, 0, &[0xFF, 0xFF], 16, false));
+    }
+
+    #[test]
+    fn collisions_only_happen_within_the_drawn_plane() {
+        let mut mmu = MMU::xo_chip();
+
+        mmu.select_planes(0b01);
+        mmu.draw_sprite(0, 0, &[0x80], 8, false);
+
+        mmu.select_planes(0b10);
+        assert!(!mmu.draw_sprite(0, 0, &[0x80], 8, false));
+        assert_eq!(mmu.rb_vram(0, 0), 0b11);
+
+        assert!(mmu.draw_sprite(0, 0, &[0x80], 8, false));
+        assert_eq!(mmu.rb_vram(0, 0), 0b01);
+    }
+
+    #[test]
+    fn both_planes_read_consecutive_sprites() {
+        let mut mmu = MMU::xo_chip();
+        mmu.select_planes(0b11);
+
+        assert!(!mmu.draw_sprite(0, 0, &[0x80, 0x40], 8, false));
+        assert_eq!(mmu.rb_vram(0, 0), 0b01);
+        assert_eq!(mmu.rb_vram(1, 0), 0b10);
+
+        assert!(mmu.draw_sprite(0, 0, &[0x00, 0x40], 8, false));
+        assert_eq!(mmu.rb_vram(1, 0), 0);
+    }
+
+    #[test]
+    fn loading_writes_anywhere() {
+        let mut mmu = MMU::default();
+        mmu.set_protection(Protection::strict());
+
+        assert_eq!(mmu.wb(0x050, 0x12), Ok(()));
+        assert_eq!(mmu.wb(0x200, 0x34), Ok(()));
+        assert_eq!(mmu.rb(0x050), Ok(0x12));
+        assert_eq!(mmu.rb(0x200), Ok(0x34));
+    }
+
+    #[test]
+    fn locked_font_ignores_writes() {
+        let mut mmu = MMU::default();
+        mmu.lock_rom();
+        let font = mmu.rb(0x000).unwrap();
+
+        assert_eq!(mmu.wb(0x000, !font), Ok(()));
+        assert_eq!(mmu.rb(0x000), Ok(font));
+        // the rest of the memory stays writable by default
+        assert_eq!(mmu.wb(0x200, 0x12), Ok(()));
+        assert_eq!(mmu.rb(0x200), Ok(0x12));
+    }
+
+    #[test]
+    fn locked_regions_fault_when_protected() {
+        let mut mmu = MMU::default();
+        mmu.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        mmu.set_protection(Protection::strict());
+
+        assert_eq!(
+            mmu.wb(0x201, 0x34),
+            Err(MemoryError::Protected {
+                offset: 0x201,
+                region: Region::Program
+            })
+        );
+        assert_eq!(mmu.rb(0x201), Ok(0x00));
+        // right past the program is ram
+        assert_eq!(mmu.wb(0x202, 0x34), Ok(()));
+    }
+
+    #[test]
+    fn unprotected_ram_still_ends() {
+        let mut mmu = MMU::default();
+        mmu.lock_rom();
+        mmu.set_protection(Protection {
+            ram: WritePolicy::Ignore,
+            ..Protection::none()
+        });
+
+        let end = mmu.memory_size();
+        assert_eq!(mmu.wb(end, 0), Err(MemoryError::OutOfRange(end)));
+    }
+
+    #[test]
+    fn programs_load_at_the_load_address() {
+        let mut mmu = MMU::new(MemoryMap::eti660()).unwrap();
+        mmu.load_rom_bytes(&[0x12, 0x34]).unwrap();
+
+        assert!(mmu.locked_rom());
+        assert_eq!(mmu.memory_size(), 0x1000);
+        assert_eq!(mmu.rw(0x600), Ok(0x1234));
+        assert_eq!(mmu.region(0x1FF), Region::Font);
+        assert_eq!(mmu.region(0x200), Region::Ram);
+        assert_eq!(mmu.region(0x5FF), Region::Ram);
+        assert_eq!(mmu.region(0x601), Region::Program);
+        assert_eq!(mmu.region(0x602), Region::Ram);
+
+        // only the fonts ignore writes below the program
+        assert_eq!(mmu.wb(0x300, 0x56), Ok(()));
+        assert_eq!(mmu.rb(0x300), Ok(0x56));
+    }
+
+    #[test]
+    fn programs_larger_than_memory_are_refused() {
+        let mut mmu = MMU::default();
+
+        assert!(mmu.load_rom_bytes(&[0; 0xE00]).is_ok());
+        match mmu.load_rom_bytes(&[0; 0xE01]) {
+            Err(LoadError::TooLarge { size, capacity }) => {
+                assert_eq!((size, capacity), (0xE01, 0xE00))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // the whole 64KiB are there for XO-CHIP
+        assert!(MMU::xo_chip().load_rom_bytes(&[0; 0xFE00]).is_ok());
+    }
+
+    #[test]
+    fn invalid_memory_maps_are_refused() {
+        assert_eq!(
+            MMU::new(MemoryMap::chip8().with_load_address(0x100)).err(),
+            Some(MapError::InvalidLoadAddress(0x100))
+        );
+        assert_eq!(
+            MMU::new(MemoryMap::chip8().with_load_address(0x1000)).err(),
+            Some(MapError::InvalidLoadAddress(0x1000))
+        );
+        assert_eq!(
+            MMU::new(MemoryMap {
+                size: 0x10001,
+                load_address: 0x200,
+            })
+            .err(),
+            Some(MapError::InvalidSize(0x10001))
+        );
+    }
+
+    #[test]
+    fn programs_replace_the_one_loaded_before() {
+        let mut mmu = MMU::default();
+        mmu.load_rom_bytes(&[0x12, 0x34, 0x56, 0x78]).unwrap();
+        mmu.load_rom_bytes(&[0x00, 0xE0]).unwrap();
+
+        assert_eq!(mmu.rw(0x200), Ok(0x00E0));
+        assert_eq!(mmu.rw(0x202), Ok(0x0000));
+    }
+
+    #[test]
+    fn programs_load_from_readers() {
+        let mut mmu = MMU::default();
+        mmu.load_rom_reader(&[0x12, 0x34][..]).unwrap();
+        assert!(mmu.locked_rom());
+        assert_eq!(mmu.rw(0x200), Ok(0x1234));
+
+        let mut mmu = MMU::default();
+        assert!(matches!(
+            mmu.load_rom_reader(&[0; 0xE01][..]),
+            Err(LoadError::TooLarge { .. })
+        ));
+    }
+
+    #[cfg(feature = "zip")]
+    fn archive(files: &[(&str, &[u8])]) -> std::io::Cursor<Vec<u8>> {
+        use std::io::Write;
+        use zip::write::FileOptions;
+
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in files {
+            let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(content).unwrap();
+        }
+
+        let mut archive = writer.finish().unwrap();
+        archive.set_position(0);
+        archive
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn programs_load_from_archives() {
+        let mut mmu = MMU::default();
+        let files: &[(&str, &[u8])] = &[("readme.txt", b"hello"), ("game/Pong.CH8", &[0x12, 0x34])];
+        mmu.load_rom_zip(archive(files)).unwrap();
+        assert_eq!(mmu.rw(0x200), Ok(0x1234));
+
+        let mut mmu = MMU::default();
+        assert!(matches!(
+            mmu.load_rom_zip(archive(&[("readme.txt", b"hello")])),
+            Err(LoadError::RomsInArchive(0))
+        ));
+        let files: &[(&str, &[u8])] = &[("a.ch8", &[0x00]), ("b.xo8", &[0x00])];
+        assert!(matches!(
+            mmu.load_rom_zip(archive(files)),
+            Err(LoadError::RomsInArchive(2))
+        ));
+        assert!(!mmu.locked_rom());
+    }
+}
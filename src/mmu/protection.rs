@@ -0,0 +1,82 @@
+use std::fmt;
+
+/// The parts of the address space that get their own write policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// The first 512 bytes: the fonts, where the interpreter itself lived
+    Font,
+    /// The program, as loaded at the load address
+    Program,
+    /// Everything else, including what lies between the fonts and a program loaded higher
+    Ram,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Font => write!(f, "font"),
+            Region::Program => write!(f, "program"),
+            Region::Ram => write!(f, "ram"),
+        }
+    }
+}
+
+/// What happens when a program writes to a region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// The write goes through
+    Allow,
+    /// The write is dropped, as if the memory was ROM
+    Ignore,
+    /// The write is refused with an error
+    Fault,
+}
+
+/// The write policy of every region, applied once the rom is locked.
+///
+/// Loading a game (before the lock) can write anywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protection {
+    pub font: WritePolicy,
+    pub program: WritePolicy,
+    pub ram: WritePolicy,
+}
+
+impl Protection {
+    /// Every region is writable, like on the original interpreters
+    pub const fn none() -> Self {
+        Self {
+            font: WritePolicy::Allow,
+            program: WritePolicy::Allow,
+            ram: WritePolicy::Allow,
+        }
+    }
+
+    /// Writes outside of the ram fault, to catch programs going astray. Self-modifying
+    /// programs and the ones keeping their variables next to their code fault too.
+    pub const fn strict() -> Self {
+        Self {
+            font: WritePolicy::Fault,
+            program: WritePolicy::Fault,
+            ram: WritePolicy::Allow,
+        }
+    }
+
+    pub fn policy(&self, region: Region) -> WritePolicy {
+        match region {
+            Region::Font => self.font,
+            Region::Program => self.program,
+            Region::Ram => self.ram,
+        }
+    }
+}
+
+/// The fonts are read-only, programs can write anywhere else
+impl Default for Protection {
+    fn default() -> Self {
+        Self {
+            font: WritePolicy::Ignore,
+            ..Self::none()
+        }
+    }
+}
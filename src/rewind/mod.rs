@@ -0,0 +1,187 @@
+//! Bounded history of machine snapshots to step back in time.
+//!
+//! Only the newest snapshot is kept whole. Every older one is stored as the run-length
+//! encoded XOR against the snapshot that followed it, which is mostly zeros since a
+//! frame rarely touches more than a few registers, some RAM and part of the screen.
+
+use std::collections::VecDeque;
+
+use crate::state::{rle_decode, rle_encode};
+
+/// Ten seconds of frames
+pub const DEFAULT_REWIND_FRAMES: usize = 600;
+
+pub struct Rewind {
+    // how many snapshots are kept, the newest included
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    // the length of the previous snapshot and its encoded delta, oldest first
+    deltas: VecDeque<(usize, Vec<u8>)>,
+}
+
+// XOR two snapshots, padding the shortest one with zeros
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let len = a.len().max(b.len());
+    (0..len)
+        .map(|i| a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0))
+        .collect()
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of snapshots held, the newest included
+    pub fn len(&self) -> usize {
+        self.deltas.len() + usize::from(self.latest.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Record a new snapshot, dropping the oldest one when the buffer is full
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            let delta = rle_encode(&xor(&previous, &snapshot));
+            self.deltas.push_back((previous.len(), delta));
+        }
+        self.latest = Some(snapshot);
+
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// The newest snapshot
+    pub fn latest(&self) -> Option<&[u8]> {
+        self.latest.as_deref()
+    }
+
+    /// Drop up to `steps` snapshots and return how many were dropped. The newest
+    /// snapshot left is the one `steps` pushes ago, or the oldest one kept.
+    pub fn rewind(&mut self, steps: usize) -> usize {
+        let mut rewound = 0;
+
+        while rewound < steps {
+            let (latest, (len, delta)) = match (self.latest.as_ref(), self.deltas.back()) {
+                (Some(latest), Some(delta)) => (latest, delta),
+                _ => break,
+            };
+
+            // the deltas are only ever made by `push`
+            let delta = rle_decode(delta).expect("corrupted rewind delta");
+            let mut previous = xor(latest, &delta);
+            previous.truncate(*len);
+
+            self.deltas.pop_back();
+            self.latest = Some(previous);
+            rewound += 1;
+        }
+
+        rewound
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_REWIND_FRAMES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Quirks, CPU};
+    use crate::machine::Machine;
+    use crate::mmu::MMU;
+
+    #[test]
+    fn rewinds_through_snapshots_of_any_length() {
+        let snapshots = [
+            vec![1, 2, 3],
+            vec![1, 2, 4, 5],
+            vec![9],
+            vec![9, 9, 9, 9, 9],
+        ];
+
+        let mut rewind = Rewind::new(10);
+        for snapshot in snapshots.iter() {
+            rewind.push(snapshot.clone());
+        }
+        assert_eq!(rewind.len(), 4);
+
+        for expected in snapshots.iter().rev().skip(1) {
+            assert_eq!(rewind.rewind(1), 1);
+            assert_eq!(rewind.latest(), Some(&expected[..]));
+        }
+        assert_eq!(rewind.rewind(1), 0);
+        assert_eq!(rewind.latest(), Some(&snapshots[0][..]));
+    }
+
+    #[test]
+    fn drops_the_oldest_snapshots() {
+        let mut rewind = Rewind::new(3);
+        for frame in 0..10u8 {
+            rewind.push(vec![frame; 64]);
+        }
+        assert_eq!(rewind.len(), 3);
+
+        assert_eq!(rewind.rewind(5), 2);
+        assert_eq!(rewind.latest(), Some(&[7; 64][..]));
+    }
+
+    #[test]
+    fn machine_rewinds_whole_frames() {
+        // ADD V0, 1; JP 0x200
+        let mut mmu = MMU::default();
+        for (i, &byte) in [0x70, 0x01, 0x12, 0x00].iter().enumerate() {
+            mmu.wb(0x200 + i, byte).unwrap();
+        }
+        mmu.lock_rom();
+
+        let mut machine = Machine::new(CPU::new(mmu, Quirks::default())).with_rewind(5);
+        let mut counters = vec![machine.cpu.v[0]];
+        for _ in 0..8 {
+            machine.run_frame().unwrap();
+            counters.push(machine.cpu.v[0]);
+        }
+        assert_eq!(machine.rewind_frames(), 4);
+
+        machine.cpu.keypad.press(0xA);
+        assert_eq!(machine.rewind(3), 3);
+        assert_eq!(machine.frames(), 5);
+        assert_eq!(machine.cpu.v[0], counters[5]);
+        assert!(machine.cpu.keypad.is_pressed(0xA));
+
+        // half a frame is dropped by going back to its start
+        machine.step().unwrap();
+        assert_eq!(machine.rewind(0), 0);
+        assert_eq!(machine.cpu.v[0], counters[5]);
+
+        assert_eq!(machine.rewind(10), 1);
+        assert_eq!(machine.cpu.v[0], counters[4]);
+
+        // loading a state starts the history over
+        let state = machine.cpu.save_state();
+        machine.run_frame().unwrap();
+        machine.load_state(&state).unwrap();
+        assert_eq!(machine.rewind_frames(), 0);
+        assert_eq!(machine.rewind(1), 0);
+        assert_eq!(machine.cpu.v[0], counters[4]);
+    }
+}
@@ -0,0 +1,401 @@
+//! Binary save-state format.
+//!
+//! A state starts with the `CH8S` magic and the version of the format that wrote it,
+//! followed by tagged chunks (a 4 bytes tag, a little-endian `u32` length and the data)
+//! and ends with the CRC-32 of everything before it.
+//!
+//! To stay compatible across versions, readers skip the chunks they don't know and
+//! ignore any bytes left at the end of a chunk, so new fields are only ever appended.
+
+use std::{error, fmt};
+
+pub const MAGIC: [u8; 4] = *b"CH8S";
+/// Version of the format written, bumped whenever a chunk changes layout: 2 added
+/// the memory map to `MEM `, 3 whether the audio pattern was loaded to `CPU `
+pub const VERSION: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    /// The data doesn't start with the save-state magic
+    BadMagic,
+    /// The checksum doesn't match the content
+    Corrupted,
+    /// The data ends in the middle of a field
+    Truncated,
+    /// A chunk every version writes is missing
+    MissingChunk([u8; 4]),
+    /// A field holds a value that can't be restored
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::Corrupted => write!(f, "save state checksum mismatch"),
+            StateError::Truncated => write!(f, "truncated save state"),
+            StateError::MissingChunk(tag) => {
+                write!(f, "missing {} chunk", String::from_utf8_lossy(tag))
+            }
+            StateError::Invalid(field) => write!(f, "invalid {} in save state", field),
+        }
+    }
+}
+
+impl error::Error for StateError {}
+
+/// CRC-32 (ISO-HDLC, as used by zlib and png) of `bytes`
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Run-length encode `bytes` (PackBits): a control byte `n` below 128 is followed by
+/// `n + 1` literal bytes, while `257 - n` repeats the byte that follows it.
+pub fn rle_encode(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut literals: Vec<u8> = Vec::new();
+
+    let flush = |literals: &mut Vec<u8>, encoded: &mut Vec<u8>| {
+        for chunk in literals.chunks(128) {
+            encoded.push(chunk.len() as u8 - 1);
+            encoded.extend_from_slice(chunk);
+        }
+        literals.clear();
+    };
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        let run = bytes[i..]
+            .iter()
+            .take(128)
+            .take_while(|&&other| other == byte)
+            .count();
+
+        if run > 2 {
+            flush(&mut literals, &mut encoded);
+            encoded.push((257 - run) as u8);
+            encoded.push(byte);
+        } else {
+            literals.extend_from_slice(&bytes[i..i + run]);
+        }
+        i += run;
+    }
+    flush(&mut literals, &mut encoded);
+
+    encoded
+}
+
+/// Decode data encoded by [`rle_encode`]
+pub fn rle_decode(encoded: &[u8]) -> Result<Vec<u8>, StateError> {
+    let mut bytes = Vec::new();
+    let mut reader = Reader::new(encoded);
+
+    while !reader.is_empty() {
+        let control = reader.u8()?;
+        if control < 128 {
+            bytes.extend_from_slice(reader.bytes(usize::from(control) + 1)?);
+        } else {
+            let byte = reader.u8()?;
+            bytes.resize(bytes.len() + 257 - usize::from(control), byte);
+        }
+    }
+
+    Ok(bytes)
+}
+
+// PackBits made only of literal runs: bigger than `rle_encode`, but a byte keeps its
+// offset in the output, which makes consecutive snapshots cheap to diff
+fn rle_literal(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(bytes.len() + bytes.len() / 128 + 1);
+    for chunk in bytes.chunks(128) {
+        encoded.push(chunk.len() as u8 - 1);
+        encoded.extend_from_slice(chunk);
+    }
+    encoded
+}
+
+/// Version of the format that wrote `state`, after checking its integrity
+pub fn version(state: &[u8]) -> Result<u16, StateError> {
+    Chunks::parse(state).map(|chunks| chunks.version)
+}
+
+/// Builds the chunks of a save state
+pub(crate) struct Writer {
+    buffer: Vec<u8>,
+    // whether the large buffers are run-length encoded or only stored
+    pub compress: bool,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend_from_slice(&VERSION.to_le_bytes());
+
+        Self {
+            buffer,
+            compress: true,
+        }
+    }
+
+    // a writer for snapshots that get diffed against each other
+    pub fn uncompressed() -> Self {
+        Self {
+            compress: false,
+            ..Self::new()
+        }
+    }
+
+    pub fn chunk(&mut self, tag: &[u8; 4], data: &[u8]) {
+        self.buffer.extend_from_slice(tag);
+        self.buffer
+            .extend_from_slice(&(data.len() as u32).to_le_bytes());
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let checksum = crc32(&self.buffer);
+        self.buffer.extend_from_slice(&checksum.to_le_bytes());
+        self.buffer
+    }
+}
+
+/// Appends little-endian fields to a chunk
+pub(crate) trait Put {
+    fn put_u8(&mut self, value: u8);
+    fn put_u16(&mut self, value: u16);
+    // a length-prefixed, run-length encoded byte buffer
+    fn put_rle(&mut self, bytes: &[u8], compress: bool);
+}
+
+impl Put for Vec<u8> {
+    fn put_u8(&mut self, value: u8) {
+        self.push(value);
+    }
+    fn put_u16(&mut self, value: u16) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+    fn put_rle(&mut self, bytes: &[u8], compress: bool) {
+        let encoded = if compress {
+            rle_encode(bytes)
+        } else {
+            rle_literal(bytes)
+        };
+        self.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+        self.extend_from_slice(&encoded);
+    }
+}
+
+/// Reads the fields of a chunk
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    pub fn rle(&mut self) -> Result<Vec<u8>, StateError> {
+        let len = self.u32()? as usize;
+        rle_decode(self.bytes(len)?)
+    }
+}
+
+/// The chunks of a verified save state
+pub(crate) struct Chunks<'a> {
+    pub version: u16,
+    chunks: Vec<([u8; 4], &'a [u8])>,
+}
+
+impl<'a> Chunks<'a> {
+    pub fn parse(state: &'a [u8]) -> Result<Self, StateError> {
+        if state.len() < MAGIC.len() || state[..MAGIC.len()] != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        if state.len() < MAGIC.len() + 2 + 4 {
+            return Err(StateError::Truncated);
+        }
+
+        let (content, checksum) = state.split_at(state.len() - 4);
+        if crc32(content).to_le_bytes() != checksum {
+            return Err(StateError::Corrupted);
+        }
+
+        let mut reader = Reader::new(&content[MAGIC.len()..]);
+        let version = reader.u16()?;
+
+        let mut chunks = Vec::new();
+        while !reader.is_empty() {
+            let mut tag = [0; 4];
+            tag.copy_from_slice(reader.bytes(4)?);
+
+            let len = reader.u32()? as usize;
+            chunks.push((tag, reader.bytes(len)?));
+        }
+
+        Ok(Self { version, chunks })
+    }
+
+    pub fn get(&self, tag: &[u8; 4]) -> Result<Reader<'a>, StateError> {
+        self.chunks
+            .iter()
+            .find(|(other, _)| other == tag)
+            .map(|&(_, data)| Reader::new(data))
+            .ok_or(StateError::MissingChunk(*tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Quirks, Status, CPU};
+    use crate::mmu::{MemoryMap, MMU};
+
+    fn blank_cpu() -> CPU {
+        let mut mmu = MMU::default();
+        mmu.lock_rom();
+
+        CPU::new(mmu, Quirks::default())
+    }
+
+    fn cpu() -> CPU {
+        let mut mmu = MMU::default();
+        mmu.wb(0x200, 0x12).unwrap();
+        mmu.wb(0x201, 0x00).unwrap();
+        mmu.lock_rom();
+
+        CPU::new(mmu, Quirks::default())
+    }
+
+    #[test]
+    fn rle_round_trips() {
+        let mut bytes = vec![0; 300];
+        bytes.extend_from_slice(&[1, 2, 2, 3, 3, 3, 4]);
+        bytes.extend((0..=255).collect::<Vec<u8>>());
+
+        let encoded = rle_encode(&bytes);
+        assert!(encoded.len() < bytes.len());
+        assert_eq!(rle_decode(&encoded).unwrap(), bytes);
+        assert_eq!(rle_decode(&rle_literal(&bytes)).unwrap(), bytes);
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut cpu = cpu();
+        cpu.v[3] = 0x2A;
+        cpu.i = 0x345;
+        cpu.delay = 7;
+        cpu.stack[0] = 0x202;
+        cpu.sp = 1;
+        cpu.status = Status::WaitingKeypress(3);
+        cpu.keypad.press(0xB);
+        cpu.bus.wb(0xABC, 0x99).unwrap();
+        cpu.bus.wb_vram(5, 6, 1);
+
+        let state = cpu.save_state();
+        assert_eq!(version(&state), Ok(VERSION));
+
+        let mut restored = blank_cpu();
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.v, cpu.v);
+        assert_eq!(restored.i, 0x345);
+        assert_eq!(restored.delay, 7);
+        assert_eq!((restored.sp, restored.stack[0]), (1, 0x202));
+        assert_eq!(restored.status, Status::WaitingKeypress(3));
+        assert!(restored.keypad.is_pressed(0xB));
+
+        let bus = &restored.bus;
+        assert!(bus.locked_rom());
+        assert_eq!(bus.rb(0xABC), Ok(0x99));
+        assert_eq!(bus.rw(0x200), Ok(0x1200));
+        assert_eq!(bus.rb_vram(5, 6), 1);
+    }
+
+    #[test]
+    fn corrupted_states_are_rejected_untouched() {
+        let mut cpu = cpu();
+        cpu.v[0] = 1;
+        let mut state = cpu.save_state();
+
+        let middle = state.len() / 2;
+        state[middle] ^= 0xFF;
+
+        cpu.v[0] = 2;
+        assert_eq!(cpu.load_state(&state), Err(StateError::Corrupted));
+        assert_eq!(cpu.v[0], 2);
+
+        assert_eq!(cpu.load_state(b"not a state"), Err(StateError::BadMagic));
+    }
+
+    #[test]
+    fn unknown_chunks_and_trailing_fields_are_skipped() {
+        let cpu = cpu();
+        let state = cpu.save_state();
+        let chunks = Chunks::parse(&state).unwrap();
+
+        // a state from a newer version with an extra field and chunk
+        let mut writer = Writer::new();
+        for (tag, data) in chunks.chunks.iter() {
+            let mut data = data.to_vec();
+            data.push(0xEE);
+            writer.chunk(tag, &data);
+        }
+        writer.chunk(b"NEW!", &[1, 2, 3]);
+
+        let mut restored = blank_cpu();
+        restored.load_state(&writer.finish()).unwrap();
+        assert_eq!(restored.bus.rw(0x200), Ok(0x1200));
+    }
+
+    #[test]
+    fn memory_map_round_trips() {
+        let mut mmu = MMU::new(MemoryMap::eti660()).unwrap();
+        mmu.wb(0x600, 0x16).unwrap();
+        mmu.lock_rom();
+        let cpu = CPU::new(mmu, Quirks::default());
+        assert_eq!(cpu.pc, 0x600);
+
+        let mut restored = blank_cpu();
+        restored.load_state(&cpu.save_state()).unwrap();
+        assert_eq!(restored.bus.map(), MemoryMap::eti660());
+        assert_eq!(restored.bus.rb(0x600), Ok(0x16));
+    }
+}
@@ -0,0 +1,457 @@
+//! Execution traces: one record per instruction, to compare runs of the emulator
+//! against each other or against other implementations.
+//!
+//! Records are written as text:
+//!
+//! ```text
+//! 42 pc=208 op=D015 v=00010000000000000000000000000000 i=050 sp=0 dt=00 st=00 ; DRW V0, V1, 5
+//! ```
+//!
+//! or as JSON lines:
+//!
+//! ```text
+//! {"cycle":42,"pc":520,"opcode":53269,"instruction":"DRW V0, V1, 5","v":[0,1,...],"i":80,"sp":0,"dt":0,"st":0}
+//! ```
+//!
+//! Both hold the state right before the instruction runs.
+
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::bus::Bus;
+use crate::cpu::CPU;
+
+/// Receives a record for every instruction the CPU runs
+pub trait Tracer {
+    fn trace(&mut self, record: &TraceRecord);
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceRecord {
+    /// Number of instructions run before this one
+    pub cycle: u64,
+    pub pc: u16,
+    /// The first word of the instruction
+    pub opcode: u16,
+    /// The instruction in Cowgod's mnemonics
+    pub instruction: String,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u16,
+    pub dt: u8,
+    pub st: u8,
+}
+
+impl TraceRecord {
+    pub(crate) fn new<B: Bus>(cpu: &CPU<B>, cycle: u64, pc: u16, instruction: String) -> Self {
+        Self {
+            cycle,
+            pc,
+            opcode: cpu.bus.fetch(usize::from(pc)).unwrap_or(0),
+            instruction,
+            v: cpu.v,
+            i: cpu.i,
+            sp: cpu.sp,
+            dt: cpu.delay,
+            st: cpu.sound,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let v: String = self.v.iter().map(|v| format!("{:02X}", v)).collect();
+        format!(
+            "{} pc={:03X} op={:04X} v={} i={:03X} sp={:X} dt={:02X} st={:02X} ; {}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            v,
+            self.i,
+            self.sp,
+            self.dt,
+            self.st,
+            self.instruction
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        let v: Vec<String> = self.v.iter().map(|v| v.to_string()).collect();
+        let mut instruction = String::new();
+        for c in self.instruction.chars() {
+            match c {
+                '"' | '\\' => write!(instruction, "\\{}", c).unwrap(),
+                c => instruction.push(c),
+            }
+        }
+
+        format!(
+            "{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"instruction\":\"{}\",\"v\":[{}],\"i\":{},\"sp\":{},\"dt\":{},\"st\":{}}}",
+            self.cycle,
+            self.pc,
+            self.opcode,
+            instruction,
+            v.join(","),
+            self.i,
+            self.sp,
+            self.dt,
+            self.st
+        )
+    }
+
+    /// Read a record written by [`TraceRecord::to_text`] or [`TraceRecord::to_json`]
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        if line.starts_with('{') {
+            parse_json(line)
+        } else {
+            parse_text(line)
+        }
+    }
+
+    /// The fields that differ from `other`, with both values
+    pub fn differences(&self, other: &TraceRecord) -> Vec<(String, String, String)> {
+        let mut differences = Vec::new();
+        let mut compare = |field: String, a: String, b: String| {
+            if a != b {
+                differences.push((field, a, b));
+            }
+        };
+
+        compare(
+            "cycle".into(),
+            self.cycle.to_string(),
+            other.cycle.to_string(),
+        );
+        compare("pc".into(), hex(self.pc, 3), hex(other.pc, 3));
+        compare("opcode".into(), hex(self.opcode, 4), hex(other.opcode, 4));
+        for x in 0..16 {
+            compare(
+                format!("V{:X}", x),
+                hex(self.v[x].into(), 2),
+                hex(other.v[x].into(), 2),
+            );
+        }
+        compare("i".into(), hex(self.i, 3), hex(other.i, 3));
+        compare("sp".into(), hex(self.sp, 1), hex(other.sp, 1));
+        compare("dt".into(), hex(self.dt.into(), 2), hex(other.dt.into(), 2));
+        compare("st".into(), hex(self.st.into(), 2), hex(other.st.into(), 2));
+
+        differences
+    }
+}
+
+fn hex(value: u16, width: usize) -> String {
+    format!("{:0width$X}", value, width = width)
+}
+
+fn parse_text(line: &str) -> Result<TraceRecord, String> {
+    let (fields, instruction) = match line.find(';') {
+        Some(n) => (&line[..n], line[n + 1..].trim()),
+        None => (line, ""),
+    };
+    let mut fields = fields.split_whitespace();
+
+    let cycle = fields
+        .next()
+        .and_then(|cycle| cycle.parse().ok())
+        .ok_or("missing the cycle")?;
+
+    let mut record = TraceRecord {
+        cycle,
+        instruction: instruction.to_string(),
+        ..Default::default()
+    };
+
+    for field in fields {
+        let mut parts = field.splitn(2, '=');
+        let (key, value) = (
+            parts.next().unwrap_or_default(),
+            parts.next().unwrap_or_default(),
+        );
+        let invalid = || format!("invalid {}: {}", key, value);
+        let number = || u16::from_str_radix(value, 16).map_err(|_| invalid());
+        let byte = || u8::from_str_radix(value, 16).map_err(|_| invalid());
+
+        match key {
+            "pc" => record.pc = number()?,
+            "op" => record.opcode = number()?,
+            "i" => record.i = number()?,
+            "sp" => record.sp = number()?,
+            "dt" => record.dt = byte()?,
+            "st" => record.st = byte()?,
+            "v" if value.len() == 32 => {
+                for (x, v) in record.v.iter_mut().enumerate() {
+                    *v = u8::from_str_radix(&value[x * 2..x * 2 + 2], 16)
+                        .map_err(|_| format!("invalid v: {}", value))?;
+                }
+            }
+            _ => return Err(format!("unexpected field: {}", field)),
+        }
+    }
+
+    Ok(record)
+}
+
+// reads the flat objects written by `to_json`, other keys are ignored
+fn parse_json(line: &str) -> Result<TraceRecord, String> {
+    let invalid = || format!("invalid trace record: {}", line);
+    let body = line
+        .strip_prefix('{')
+        .and_then(|line| line.strip_suffix('}'))
+        .ok_or_else(invalid)?;
+
+    let mut record = TraceRecord::default();
+    let mut rest = body.trim();
+    while !rest.is_empty() {
+        // "key":
+        let key_end = rest[1..].find('"').ok_or_else(invalid)? + 1;
+        let key = &rest[1..key_end];
+        rest = rest[key_end + 1..]
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or_else(invalid)?
+            .trim_start();
+
+        // the value runs up to the next comma outside of strings and arrays
+        let mut end = rest.len();
+        let (mut in_string, mut escaped, mut depth) = (false, false, 0);
+        for (n, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if in_string => escaped = true,
+                '"' => in_string = !in_string,
+                '[' if !in_string => depth += 1,
+                ']' if !in_string => depth -= 1,
+                ',' if !in_string && depth == 0 => {
+                    end = n;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let value = rest[..end].trim();
+        rest = rest[end..].trim_start_matches(',').trim_start();
+
+        let number = || value.parse::<u64>().map_err(|_| invalid());
+        let word = || u16::try_from(number()?).map_err(|_| invalid());
+        let byte = || u8::try_from(number()?).map_err(|_| invalid());
+        match key {
+            "cycle" => record.cycle = number()?,
+            "pc" => record.pc = word()?,
+            "opcode" => record.opcode = word()?,
+            "i" => record.i = word()?,
+            "sp" => record.sp = word()?,
+            "dt" => record.dt = byte()?,
+            "st" => record.st = byte()?,
+            "instruction" => {
+                let string = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .ok_or_else(invalid)?;
+                record.instruction = string.replace("\\\"", "\"").replace("\\\\", "\\");
+            }
+            "v" => {
+                let values = value
+                    .strip_prefix('[')
+                    .and_then(|value| value.strip_suffix(']'))
+                    .ok_or_else(invalid)?
+                    .split(',')
+                    .map(|v| v.trim().parse::<u8>().map_err(|_| invalid()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if values.len() != 16 {
+                    return Err(invalid());
+                }
+                record.v.copy_from_slice(&values);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(record)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Json,
+}
+
+/// Writes the records as lines of text or JSON
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, format: TraceFormat) -> Self {
+        Self { out, format }
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        let line = match self.format {
+            TraceFormat::Text => record.to_text(),
+            TraceFormat::Json => record.to_json(),
+        };
+        // a trace that can't be written isn't worth stopping the emulation for
+        let _ = writeln!(self.out, "{}", line);
+    }
+}
+
+/// Where two traces first disagree
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    /// The records at this (0-based) index differ
+    Record {
+        index: usize,
+        a: TraceRecord,
+        b: TraceRecord,
+    },
+    /// One of the traces ends after `index` records while the other goes on
+    Length { index: usize, a_ended: bool },
+}
+
+/// Compare two traces record by record
+pub fn first_divergence<A, B>(a: A, b: B) -> Option<Divergence>
+where
+    A: IntoIterator<Item = TraceRecord>,
+    B: IntoIterator<Item = TraceRecord>,
+{
+    let (mut a, mut b) = (a.into_iter(), b.into_iter());
+
+    for index in 0.. {
+        match (a.next(), b.next()) {
+            (Some(a), Some(b)) => {
+                if !a.differences(&b).is_empty() {
+                    return Some(Divergence::Record { index, a, b });
+                }
+            }
+            (None, Some(_)) => {
+                return Some(Divergence::Length {
+                    index,
+                    a_ended: true,
+                })
+            }
+            (Some(_), None) => {
+                return Some(Divergence::Length {
+                    index,
+                    a_ended: false,
+                })
+            }
+            (None, None) => break,
+        }
+    }
+
+    None
+}
+
+/// Read a trace, one record per line
+pub fn read_trace<R: io::BufRead>(reader: R) -> Result<Vec<TraceRecord>, String> {
+    reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(n, line)| {
+            let line = line.map_err(|error| error.to_string())?;
+            TraceRecord::parse(&line).map_err(|error| format!("line {}: {}", n + 1, error))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Quirks;
+    use crate::mmu::MMU;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // keeps the records in memory
+    struct Recorder(Rc<RefCell<Vec<TraceRecord>>>);
+
+    impl Tracer for Recorder {
+        fn trace(&mut self, record: &TraceRecord) {
+            self.0.borrow_mut().push(record.clone());
+        }
+    }
+
+    fn trace(program: &[u8], cycles: usize) -> Vec<TraceRecord> {
+        let mut mmu = MMU::default();
+        for (i, &byte) in program.iter().enumerate() {
+            mmu.wb(0x200 + i, byte).unwrap();
+        }
+        mmu.lock_rom();
+
+        let records = Rc::new(RefCell::new(Vec::new()));
+        let mut cpu = CPU::new(mmu, Quirks::default());
+        cpu.set_tracer(Some(Box::new(Recorder(records.clone()))));
+        for _ in 0..cycles {
+            cpu.cycle().unwrap();
+        }
+
+        records.take()
+    }
+
+    #[test]
+    fn records_the_state_before_each_instruction() {
+        // LD V3, #2A; ADD V3, #01; JP 200
+        let records = trace(&[0x63, 0x2A, 0x73, 0x01, 0x12, 0x00], 4);
+
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            (records[1].cycle, records[1].pc, records[1].opcode),
+            (1, 0x202, 0x7301)
+        );
+        assert_eq!(records[1].instruction, "ADD V3, #01");
+        assert_eq!(records[1].v[3], 0x2A);
+        assert_eq!((records[3].pc, records[3].v[3]), (0x200, 0x2B));
+    }
+
+    #[test]
+    fn text_and_json_round_trip() {
+        let records = trace(&[0x63, 0x2A, 0xA1, 0x23, 0x12, 0x00], 3);
+
+        for record in records {
+            assert_eq!(TraceRecord::parse(&record.to_text()), Ok(record.clone()));
+            assert_eq!(TraceRecord::parse(&record.to_json()), Ok(record));
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let record = TraceRecord::default();
+
+        let text = record.to_text().replace("dt=00", "dt=1FF");
+        assert!(TraceRecord::parse(&text).is_err());
+        let json = record.to_json().replace("\"st\":0", "\"st\":256");
+        assert!(TraceRecord::parse(&json).is_err());
+        let json = record.to_json().replace("\"pc\":0", "\"pc\":65536");
+        assert!(TraceRecord::parse(&json).is_err());
+    }
+
+    #[test]
+    fn finds_the_first_divergence() {
+        let a = trace(&[0x63, 0x2A, 0x73, 0x01, 0x12, 0x00], 6);
+        let b = trace(&[0x63, 0x2A, 0x73, 0x02, 0x12, 0x00], 6);
+
+        assert_eq!(first_divergence(a.clone(), a.clone()), None);
+        match first_divergence(a.clone(), b) {
+            Some(Divergence::Record { index, a, b }) => {
+                assert_eq!(index, 1);
+                assert_eq!(
+                    a.differences(&b),
+                    vec![("opcode".into(), "7301".into(), "7302".into())]
+                );
+            }
+            divergence => panic!("unexpected divergence: {:?}", divergence),
+        }
+        assert_eq!(
+            first_divergence(a[..2].to_vec(), a),
+            Some(Divergence::Length {
+                index: 2,
+                a_ended: true
+            })
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Runs the test ROMs in `tests/roms` without a display and compares the screen they
+//! end on with the golden framebuffers in `tests/golden`.
+//!
+//! The standard test ROMs (corax+, flags, quirks and keypad from Timendus's suite) are
+//! not vendored yet. The ROMs here are written for this repository and their golden
+//! files were recorded from this emulator, so the suite only guards against
+//! regressions: it doesn't check the emulator against known-good screens.
+//!
+//! ROMs are either assembled from `.asm` sources or loaded as is from `.ch8` files,
+//! so Timendus's `.ch8` files can be added next to the in-repo ones with a case and a
+//! golden file taken from a known-good screenshot.
+//! Run with `UPDATE_GOLDEN=1` to write the current screens as the new golden files,
+//! after checking them by hand.
+
+extern crate chip8;
+
+use chip8::assembler::Assembler;
+use chip8::cpu::{Quirks, CPU};
+use chip8::machine::{Machine, Timing};
+use chip8::mmu::MMU;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+struct Case {
+    rom: &'static str,
+    golden: &'static str,
+    quirks: Quirks,
+    timing: Timing,
+    frames: u64,
+    // (frame, key, pressed)
+    keys: &'static [(u64, u8, bool)],
+}
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(dir)
+        .join(file)
+}
+
+fn load(rom: &str) -> MMU {
+    let rom_path = path("roms", rom);
+    let mut mmu = MMU::default();
+
+    if rom.ends_with(".asm") {
+        let bytes = Assembler::new()
+            .assemble_file(&rom_path)
+            .unwrap_or_else(|error| panic!("could not assemble {}: {}", rom, error));
+        mmu.load_rom_bytes(&bytes)
+            .unwrap_or_else(|error| panic!("could not load {}: {}", rom, error));
+    } else {
+        mmu.load_game(&rom_path)
+            .unwrap_or_else(|error| panic!("could not load {}: {}", rom, error));
+    }
+
+    mmu
+}
+
+// the screen as text, like `chip8-headless --screen ascii`
+fn screen(cpu: &CPU) -> String {
+    const CHARS: [char; 4] = ['.', '#', '+', '@'];
+
+    let bus = &cpu.bus;
+    let width = bus.width();
+    bus.vram[..width * bus.height()]
+        .chunks(width)
+        .map(|row| {
+            let mut line: String = row
+                .iter()
+                .map(|&pixel| CHARS[usize::from(pixel & 0b11)])
+                .collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+fn run(case: Case) {
+    let mut machine = Machine::new(CPU::new(load(case.rom), case.quirks)).with_timing(case.timing);
+
+    let mut keys = case.keys.iter().peekable();
+    while machine.frames() < case.frames {
+        while let Some(&&(_, key, pressed)) =
+            keys.peek().filter(|event| event.0 <= machine.frames())
+        {
+            if pressed {
+                machine.cpu.keypad.press(key);
+            } else {
+                machine.cpu.keypad.release(key);
+            }
+            keys.next();
+        }
+
+        machine
+            .run_frame()
+            .unwrap_or_else(|error| panic!("{} faulted: {}", case.rom, error));
+    }
+
+    let actual = screen(&machine.cpu);
+    let golden_path = path("golden", case.golden);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden_path, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&golden_path)
+        .unwrap_or_else(|error| panic!("could not read {}: {}", case.golden, error));
+    if actual != expected {
+        let rows: Vec<String> = expected
+            .lines()
+            .zip(actual.lines())
+            .enumerate()
+            .filter(|(_, (expected, actual))| expected != actual)
+            .map(|(y, (expected, actual))| {
+                format!("{:2} expected {}\n   actual   {}", y, expected, actual)
+            })
+            .collect();
+        panic!(
+            "{} doesn't match {}:\n{}\n\nfull screen:\n{}",
+            case.rom,
+            case.golden,
+            rows.join("\n"),
+            actual
+        );
+    }
+}
+
+#[test]
+fn opcodes() {
+    run(Case {
+        rom: "opcodes.asm",
+        golden: "opcodes.txt",
+        quirks: Quirks::cosmac_vip(),
+        timing: Timing::Fixed,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn flags() {
+    run(Case {
+        rom: "flags.asm",
+        golden: "flags.txt",
+        quirks: Quirks::cosmac_vip(),
+        timing: Timing::Fixed,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn quirks_cosmac_vip() {
+    run(Case {
+        rom: "quirks.asm",
+        golden: "quirks-vip.txt",
+        quirks: Quirks::cosmac_vip(),
+        timing: Timing::Fixed,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn quirks_cosmac_vip_timing() {
+    run(Case {
+        rom: "quirks.asm",
+        golden: "quirks-vip.txt",
+        quirks: Quirks::cosmac_vip(),
+        timing: Timing::CosmacVip,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn quirks_chip48() {
+    run(Case {
+        rom: "quirks.asm",
+        golden: "quirks-chip48.txt",
+        quirks: Quirks::chip48(),
+        timing: Timing::Fixed,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn quirks_superchip() {
+    run(Case {
+        rom: "quirks.asm",
+        golden: "quirks-schip.txt",
+        quirks: Quirks::superchip(),
+        timing: Timing::Fixed,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn quirks_xo_chip() {
+    run(Case {
+        rom: "quirks.asm",
+        golden: "quirks-xochip.txt",
+        quirks: Quirks::xo_chip(),
+        timing: Timing::Fixed,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn keypad() {
+    run(Case {
+        rom: "keypad.asm",
+        golden: "keypad.txt",
+        quirks: Quirks::cosmac_vip(),
+        timing: Timing::Fixed,
+        frames: 60,
+        keys: &[
+            (10, 0x5, true),
+            (15, 0x5, false),
+            (25, 0x9, true),
+            (40, 0x9, false),
+        ],
+    });
+}
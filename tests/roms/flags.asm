@@ -0,0 +1,141 @@
+; Checks the result and VF after the arithmetic opcodes, one cell each: a tick when
+; both are right, a cross otherwise. Row by row:
+;
+;  1  8XY4 no carry       2  8XY4 carry          3  8XY5 no borrow     4  8XY5 borrow
+;  5  8XY5 equal          6  8XY7 no borrow      7  8XY7 borrow        8  8XY6 shifts 1 out
+;  9  8XY6 shifts 0 out  10  8XYE shifts 1 out  11  8XYE shifts 0 out
+; 12  8XY4 into VF       13  8XY5 into VF       14  8XY6 into VF     15  8XYE into VF
+;
+; The shifts use the same value in both registers so they pass with either shift quirk.
+; When VF is the destination, the flag overwrites the result.
+
+    CLS
+    LD VA, 0
+    LD VB, 0
+
+    ; 8XY4
+    LD V0, #10
+    LD V1, #20
+    ADD V0, V1
+    LD V2, #30
+    LD V3, 0
+    CALL check
+
+    LD V0, #F0
+    LD V1, #20
+    ADD V0, V1
+    LD V2, #10
+    LD V3, 1
+    CALL check
+
+    ; 8XY5
+    LD V0, #30
+    LD V1, #10
+    SUB V0, V1
+    LD V2, #20
+    LD V3, 1
+    CALL check
+
+    LD V0, #10
+    LD V1, #30
+    SUB V0, V1
+    LD V2, #E0
+    LD V3, 0
+    CALL check
+
+    LD V0, #10
+    LD V1, #10
+    SUB V0, V1
+    LD V2, 0
+    LD V3, 1
+    CALL check
+
+    ; 8XY7
+    LD V0, #10
+    LD V1, #30
+    SUBN V0, V1
+    LD V2, #20
+    LD V3, 1
+    CALL check
+
+    LD V0, #30
+    LD V1, #10
+    SUBN V0, V1
+    LD V2, #E0
+    LD V3, 0
+    CALL check
+
+    ; 8XY6
+    LD V0, #05
+    LD V1, #05
+    SHR V0, V1
+    LD V2, #02
+    LD V3, 1
+    CALL check
+
+    LD V0, #04
+    LD V1, #04
+    SHR V0, V1
+    LD V2, #02
+    LD V3, 0
+    CALL check
+
+    ; 8XYE
+    LD V0, #81
+    LD V1, #81
+    SHL V0, V1
+    LD V2, #02
+    LD V3, 1
+    CALL check
+
+    LD V0, #41
+    LD V1, #41
+    SHL V0, V1
+    LD V2, #82
+    LD V3, 0
+    CALL check
+
+    ; VF as the destination
+    LD VF, #F0
+    LD V1, #20
+    ADD VF, V1
+    LD V0, VF
+    LD V2, 1
+    LD V3, 1
+    CALL check
+
+    LD VF, #10
+    LD V1, #30
+    SUB VF, V1
+    LD V0, VF
+    LD V2, 0
+    LD V3, 0
+    CALL check
+
+    LD VF, #03
+    SHR VF, VF
+    LD V0, VF
+    LD V2, 1
+    LD V3, 1
+    CALL check
+
+    LD VF, #81
+    SHL VF, VF
+    LD V0, VF
+    LD V2, 1
+    LD V3, 1
+    CALL check
+
+end:
+    JP end
+
+; reports whether V0 = V2 and VF = V3
+check:
+    LD VE, 1
+    SE V0, V2
+    LD VE, 0
+    SE VF, V3
+    LD VE, 0
+    JP report
+
+    include "report.asm"
@@ -0,0 +1,33 @@
+; Waits for key presses, drawing something after each step:
+;
+; 1  FX0A, the hex digit of the key it got
+; 2  EX9E, a 9 once the 9 key is held
+; 3  EXA1, a tick once the 9 key is released again
+
+    CLS
+    LD VA, 0
+    LD VB, 0
+
+    ; FX0A
+    LD VE, K
+    CALL digit
+
+    ; EX9E
+    LD V1, 9
+wait_press:
+    SKP V1
+    JP wait_press
+    LD VE, 9
+    CALL digit
+
+    ; EXA1
+wait_release:
+    SKNP V1
+    JP wait_release
+    LD VE, 1
+    CALL report
+
+end:
+    JP end
+
+    include "report.asm"
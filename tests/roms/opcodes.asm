@@ -0,0 +1,247 @@
+; Checks the result of every opcode that doesn't depend on a quirk, one cell each:
+; a tick when it passed, a cross when it failed. Row by row:
+;
+;  1  3XNN skips       2  3XNN doesn't skip   3  4XNN             4  5XY0
+;  5  9XY0             6  7XNN wraps          7  8XY0             8  8XY1
+;  9  8XY2            10  8XY3               11  8XY4            12  8XY5
+; 13  8XY6            14  8XY7               15  8XYE            16  ANNN + FX1E
+; 17  FX55 + FX65     18  FX33               19  2NNN + 00EE     20  BNNN
+; 21  FX29            22  FX15 + FX07        23  DXYN collisions
+
+    CLS
+    LD VA, 0
+    LD VB, 0
+
+    ; 3XNN
+    LD V0, 5
+    LD VE, 1
+    SE V0, 5
+    LD VE, 0
+    CALL report
+
+    LD VE, 0
+    SE V0, 6
+    LD VE, 1
+    CALL report
+
+    ; 4XNN
+    LD VE, 1
+    SNE V0, 6
+    LD VE, 0
+    CALL report
+
+    ; 5XY0
+    LD V1, 5
+    LD VE, 1
+    SE V0, V1
+    LD VE, 0
+    CALL report
+
+    ; 9XY0
+    LD V1, 6
+    LD VE, 1
+    SNE V0, V1
+    LD VE, 0
+    CALL report
+
+    ; 7XNN
+    LD V0, #FE
+    ADD V0, 3
+    LD VE, 1
+    SE V0, 1
+    LD VE, 0
+    CALL report
+
+    ; 8XY0
+    LD V1, #42
+    LD V0, V1
+    LD VE, 1
+    SE V0, #42
+    LD VE, 0
+    CALL report
+
+    ; 8XY1
+    LD V0, #F0
+    LD V1, #0F
+    OR V0, V1
+    LD VE, 1
+    SE V0, #FF
+    LD VE, 0
+    CALL report
+
+    ; 8XY2
+    LD V0, #F3
+    LD V1, #3F
+    AND V0, V1
+    LD VE, 1
+    SE V0, #33
+    LD VE, 0
+    CALL report
+
+    ; 8XY3
+    LD V0, #F3
+    XOR V0, V1
+    LD VE, 1
+    SE V0, #CC
+    LD VE, 0
+    CALL report
+
+    ; 8XY4
+    LD V0, #F0
+    LD V1, #20
+    ADD V0, V1
+    LD VE, 1
+    SE V0, #10
+    LD VE, 0
+    CALL report
+
+    ; 8XY5
+    LD V0, #20
+    LD V1, #30
+    SUB V0, V1
+    LD VE, 1
+    SE V0, #F0
+    LD VE, 0
+    CALL report
+
+    ; 8XY6, with the same value in both registers for either shift quirk
+    LD V0, #85
+    LD V1, #85
+    SHR V0, V1
+    LD VE, 1
+    SE V0, #42
+    LD VE, 0
+    CALL report
+
+    ; 8XY7
+    LD V0, #10
+    LD V1, #30
+    SUBN V0, V1
+    LD VE, 1
+    SE V0, #20
+    LD VE, 0
+    CALL report
+
+    ; 8XYE
+    LD V0, #85
+    LD V1, #85
+    SHL V0, V1
+    LD VE, 1
+    SE V0, #0A
+    LD VE, 0
+    CALL report
+
+    ; ANNN + FX1E
+    LD I, table
+    LD V0, 2
+    ADD I, V0
+    LD V0, [I]
+    LD VE, 1
+    SE V0, #33
+    LD VE, 0
+    CALL report
+
+    ; FX55 + FX65
+    LD V0, #11
+    LD V1, #22
+    LD V2, #33
+    LD I, scratch
+    LD [I], V2
+    LD V0, 0
+    LD V2, 0
+    LD I, scratch
+    LD V2, [I]
+    LD VE, 1
+    SE V0, #11
+    LD VE, 0
+    SE V2, #33
+    LD VE, 0
+    CALL report
+
+    ; FX33
+    LD V0, 137
+    LD I, scratch
+    LD B, V0
+    LD V2, [I]
+    LD VE, 1
+    SE V0, 1
+    LD VE, 0
+    SE V1, 3
+    LD VE, 0
+    SE V2, 7
+    LD VE, 0
+    CALL report
+
+    ; 2NNN + 00EE
+    LD V0, 0
+    CALL subroutine
+    LD VE, 1
+    SE V0, #99
+    LD VE, 0
+    CALL report
+
+    ; BNNN, with the same offset in every register the jumping quirk could use
+    LD V0, 2
+    LD V1, 2
+    LD V2, 2
+    LD V3, 2
+    LD V4, 2
+    JP V0, jumps
+jumped:
+    CALL report
+
+    ; FX29, the first two rows of the 7
+    LD V0, 7
+    LD F, V0
+    LD V1, [I]
+    LD VE, 1
+    SE V0, #F0
+    LD VE, 0
+    SE V1, #10
+    LD VE, 0
+    CALL report
+
+    ; FX15 + FX07
+    LD V0, 10
+    LD DT, V0
+    LD V1, DT
+    LD VE, 1
+    SNE V1, 0
+    LD VE, 0
+    CALL report
+
+    ; DXYN, drawing the same sprite twice erases it
+    LD V0, 0
+    LD V1, 26
+    LD I, pass
+    LD VE, 1
+    DRW V0, V1, 5
+    SE VF, 0
+    LD VE, 0
+    DRW V0, V1, 5
+    SE VF, 1
+    LD VE, 0
+    CALL report
+
+end:
+    JP end
+
+subroutine:
+    LD V0, #99
+    RET
+
+jumps:
+    JP jump_failed
+    LD VE, 1
+    JP jumped
+jump_failed:
+    LD VE, 0
+    JP jumped
+
+table:
+    db #11, #22, #33
+
+scratch:
+    db 0, 0, 0
+
+    include "report.asm"
@@ -0,0 +1,105 @@
+; Shows which quirks the interpreter has, as a 1 or a 0 for each:
+;
+;   vF reset   memory   shifting   jumping   clipping   display wait
+;
+; vF reset      8XY1 sets VF to 0
+; memory        FX65 moves I past the registers it loads
+; shifting      8XY6 shifts VY into VX
+; jumping       BXNN jumps to XNN + VX
+; clipping      sprites are cut at the right edge instead of wrapping around
+; display wait  DXYN waits for the vertical blank
+
+    CLS
+    LD VA, 0
+    LD VB, 0
+
+    ; vF reset
+    LD VF, 1
+    LD V0, 0
+    OR V0, V0
+    LD VE, 1
+    SE VF, 0
+    LD VE, 0
+    CALL digit
+
+    ; memory
+    LD I, bytes
+    LD V0, [I]
+    LD V0, [I]
+    LD VE, 1
+    SE V0, #BB
+    LD VE, 0
+    CALL digit
+
+    ; shifting
+    LD V0, #01
+    LD V1, #04
+    SHR V0, V1
+    LD VE, 1
+    SE V0, #02
+    LD VE, 0
+    CALL digit
+
+    ; jumping, VX is one of V2 or V3 depending on where the table ends up
+    LD V0, 0
+    LD V2, 2
+    LD V3, 2
+    JP V0, jumps
+jumped:
+    CALL digit
+
+    ; clipping, a line that wraps around collides with one at the left edge
+    LD I, line
+    LD V0, 60
+    LD V1, 31
+    LD V2, 0
+    DRW V0, V1, 1
+    DRW V2, V1, 1
+    LD VE, 1
+    SE VF, 0
+    LD VE, 0
+    ; erase both lines
+    DRW V0, V1, 1
+    DRW V2, V1, 1
+    CALL digit
+
+    ; display wait, only a few sprites can be drawn in 3 frames when it's on
+    LD I, blank
+    LD V0, 0
+    LD V5, 0
+    LD V6, 3
+    LD DT, V6
+wait:
+    DRW V0, V0, 1
+    ADD V5, 1
+    LD V6, DT
+    SE V6, 0
+    JP wait
+    LD V6, 6
+    SUB V5, V6
+    LD VE, 1
+    SE VF, 0
+    LD VE, 0
+    CALL digit
+
+end:
+    JP end
+
+jumps:
+    JP not_jumping
+    LD VE, 1
+    JP jumped
+not_jumping:
+    LD VE, 0
+    JP jumped
+
+bytes:
+    db #AA, #BB
+
+line:
+    db #FF
+
+blank:
+    db 0
+
+    include "report.asm"
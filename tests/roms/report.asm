@@ -0,0 +1,37 @@
+; Shared by the test ROMs: draws the result of a check and moves to the next cell.
+;
+; VA, VB  position of the next cell (10 cells per row)
+; VE      1 when the check passed, anything else when it failed
+
+report:
+    LD I, pass
+    SE VE, 1
+    LD I, fail
+    DRW VA, VB, 5
+    ADD VA, 6
+    SE VA, 60
+    RET
+    LD VA, 0
+    ADD VB, 6
+    RET
+
+; draws the hex digit in VE instead
+digit:
+    LD F, VE
+    DRW VA, VB, 5
+    ADD VA, 6
+    RET
+
+pass:
+    db %00001000
+    db %00010000
+    db %10100000
+    db %01000000
+    db %00000000
+
+fail:
+    db %10001000
+    db %01010000
+    db %00100000
+    db %01010000
+    db %10001000